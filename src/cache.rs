@@ -0,0 +1,277 @@
+use std::collections::BTreeMap;
+use std::fs::{File, Permissions};
+use std::io::Read;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{Context, Result};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+
+use crate::incremental::expand_patterns;
+use crate::models::TaskNode;
+
+/// Directory, next to jakefile.toml, holding the fingerprint of the last successful run
+/// of each task.
+const CACHE_DIR: &str = ".jake/cache";
+/// Default directory, next to jakefile.toml, of the artifact cache.
+const ARTIFACTS_DIR: &str = ".jake/artifacts";
+/// Environment variable overriding the directory of the artifact cache.
+const CACHE_DIR_VAR: &str = "JAKE_CACHE_DIR";
+
+/// What a task's run depends on: its resolved command and settings, and the content of
+/// its source files.
+pub struct Fingerprint {
+    /// Hash of everything below, compared to decide whether the task changed.
+    pub hash: String,
+    pub command: String,
+    /// Hash of each source file, by path.
+    pub sources: BTreeMap<String, String>,
+}
+
+impl Fingerprint {
+    pub fn of(task_node: &TaskNode) -> Result<Self> {
+        let mut sources = BTreeMap::new();
+        for path in expand_patterns(&task_node.sources, "sources")?
+            .iter()
+            .flatten()
+        {
+            sources.insert(path.to_string_lossy().to_string(), hash_file(path)?);
+        }
+        let options = &task_node.options;
+        let inputs = json!({
+            "command": task_node.command,
+            "args": task_node.args,
+            "params": task_node.params,
+            "env": options.env,
+            "dir": options.dir,
+            "shell": options.shell,
+            "interpreter": options.interpreter,
+            "outputs": task_node.outputs,
+            "sources": sources,
+        });
+        Ok(Self {
+            hash: hash_bytes(inputs.to_string().as_bytes()),
+            command: task_node.command.clone(),
+            sources,
+        })
+    }
+
+    fn to_json(&self) -> Value {
+        json!({
+            "hash": self.hash,
+            "command": self.command,
+            "sources": self.sources,
+        })
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        let sources = value
+            .get("sources")?
+            .as_object()?
+            .iter()
+            .map(|(path, hash)| Some((path.clone(), hash.as_str()?.to_string())))
+            .collect::<Option<BTreeMap<String, String>>>()?;
+        Some(Self {
+            hash: value.get("hash")?.as_str()?.to_string(),
+            command: value.get("command")?.as_str()?.to_string(),
+            sources,
+        })
+    }
+}
+
+/// Fingerprints of the last successful run of each task, stored in `.jake/cache`.
+pub struct FingerprintCache {
+    dir: PathBuf,
+}
+
+impl FingerprintCache {
+    pub fn new(jakefile_dir: &Path) -> Self {
+        Self {
+            dir: jakefile_dir.join(CACHE_DIR),
+        }
+    }
+
+    /// A record describes the run that produced the outputs currently on disk, so a
+    /// task's entry is shared by all its invocations that write the same outputs: running
+    /// it with other parameters replaces the record. Tasks without outputs have an entry
+    /// per invocation instead. Entries are named after a hash, since parameters and
+    /// arguments may contain any character.
+    fn entry(&self, task_node: &TaskNode) -> PathBuf {
+        let key = if task_node.outputs.is_empty() {
+            json!([task_node.id])
+        } else {
+            json!([task_node.name, task_node.outputs])
+        };
+        self.dir.join(hash_bytes(key.to_string().as_bytes()))
+    }
+
+    /// The fingerprint of the last successful run of a node, if any. Unreadable entries
+    /// are treated as missing, so that the task simply runs again.
+    pub fn load(&self, task_node: &TaskNode) -> Option<Fingerprint> {
+        let content = std::fs::read_to_string(self.entry(task_node)).ok()?;
+        let value: Value = serde_json::from_str(&content).ok()?;
+        Fingerprint::from_json(&value)
+    }
+
+    pub fn store(&self, task_node: &TaskNode, fingerprint: &Fingerprint) -> Result<()> {
+        std::fs::create_dir_all(&self.dir)
+            .with_context(|| format!("Could not create {}", self.dir.display()))?;
+        let entry = self.entry(task_node);
+        std::fs::write(&entry, fingerprint.to_json().to_string())
+            .with_context(|| format!("Could not write {}", entry.display()))
+    }
+
+    /// Forgets the last successful run of a node, so that it runs again.
+    pub fn remove(&self, task_node: &TaskNode) -> Result<()> {
+        let entry = self.entry(task_node);
+        match std::fs::remove_file(&entry) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                Err(e).with_context(|| format!("Could not remove {}", entry.display()))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Outputs of successful runs, stored by fingerprint so that they can be restored when
+/// a task runs again with the same inputs.
+///
+/// Files are stored once under `blobs/`, named after the hash of their content, and each
+/// fingerprint has a manifest listing the path, content hash and mode of its outputs.
+pub struct ArtifactCache {
+    dir: PathBuf,
+}
+
+impl ArtifactCache {
+    /// The cache in `$JAKE_CACHE_DIR`, or in `.jake/artifacts` next to jakefile.toml.
+    pub fn from_env(jakefile_dir: &Path) -> Self {
+        let dir = match std::env::var_os(CACHE_DIR_VAR) {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => jakefile_dir.join(ARTIFACTS_DIR),
+        };
+        Self { dir }
+    }
+
+    fn manifest(&self, fingerprint: &Fingerprint) -> PathBuf {
+        self.dir.join(format!("{}.json", fingerprint.hash))
+    }
+
+    fn blob(&self, hash: &str) -> PathBuf {
+        self.dir.join("blobs").join(hash)
+    }
+
+    /// Stores the files matched by a task's output patterns. Nothing is stored when one
+    /// of the patterns matches no file, since restoring them would be incomplete.
+    pub fn store(&self, fingerprint: &Fingerprint, outputs: &[String]) -> Result<()> {
+        let outputs = expand_patterns(outputs, "outputs")?;
+        if outputs.iter().any(Vec::is_empty) {
+            return Ok(());
+        }
+        std::fs::create_dir_all(self.dir.join("blobs"))
+            .with_context(|| format!("Could not create {}", self.dir.display()))?;
+        let mut files = vec![];
+        for path in outputs.iter().flatten() {
+            let hash = hash_file(path)?;
+            let blob = self.blob(&hash);
+            if !blob.exists() {
+                // copied under a temporary name first, so that a blob is always complete
+                static COUNTER: AtomicUsize = AtomicUsize::new(0);
+                let partial = blob.with_extension(format!(
+                    "{}-{}",
+                    std::process::id(),
+                    COUNTER.fetch_add(1, Ordering::Relaxed)
+                ));
+                std::fs::copy(path, &partial)
+                    .and_then(|_| std::fs::rename(&partial, &blob))
+                    .with_context(|| format!("Could not store {} in the cache", path.display()))?;
+            }
+            let mode = std::fs::metadata(path)?.permissions().mode();
+            files.push(json!({
+                "path": path.to_string_lossy(),
+                "hash": hash,
+                "mode": mode,
+            }));
+        }
+        // written last, so that a manifest only refers to blobs that exist
+        let manifest = self.manifest(fingerprint);
+        std::fs::write(&manifest, json!({ "files": files }).to_string())
+            .with_context(|| format!("Could not write {}", manifest.display()))
+    }
+
+    /// Whether the outputs stored for a fingerprint can be restored.
+    pub fn contains(&self, fingerprint: &Fingerprint) -> bool {
+        self.load_manifest(fingerprint)
+            .is_some_and(|files| files.iter().all(|(_, hash, _)| self.blob(hash).is_file()))
+    }
+
+    /// Restores the outputs stored for a fingerprint, replacing the files currently
+    /// matched by the output patterns. Returns whether the fingerprint was in the cache.
+    pub fn restore(&self, fingerprint: &Fingerprint, outputs: &[String]) -> Result<bool> {
+        let Some(files) = self.load_manifest(fingerprint) else {
+            return Ok(false);
+        };
+        if files.iter().any(|(_, hash, _)| !self.blob(hash).is_file()) {
+            return Ok(false);
+        }
+        for path in expand_patterns(outputs, "outputs")?.iter().flatten() {
+            std::fs::remove_file(path)
+                .with_context(|| format!("Could not remove {}", path.display()))?;
+        }
+        for (path, hash, mode) in files {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)
+                    .with_context(|| format!("Could not create {}", parent.display()))?;
+            }
+            std::fs::copy(self.blob(&hash), &path)
+                .and_then(|_| std::fs::set_permissions(&path, Permissions::from_mode(mode)))
+                .with_context(|| format!("Could not restore {} from the cache", path.display()))?;
+        }
+        Ok(true)
+    }
+
+    /// The files listed in the manifest of a fingerprint, or `None` if it is missing or
+    /// unreadable.
+    fn load_manifest(&self, fingerprint: &Fingerprint) -> Option<Vec<(PathBuf, String, u32)>> {
+        let content = std::fs::read_to_string(self.manifest(fingerprint)).ok()?;
+        let value: Value = serde_json::from_str(&content).ok()?;
+        value
+            .get("files")?
+            .as_array()?
+            .iter()
+            .map(|file| {
+                Some((
+                    PathBuf::from(file.get("path")?.as_str()?),
+                    file.get("hash")?.as_str()?.to_string(),
+                    u32::try_from(file.get("mode")?.as_u64()?).ok()?,
+                ))
+            })
+            .collect()
+    }
+}
+
+fn hash_file(path: &Path) -> Result<String> {
+    let mut hasher = Sha256::new();
+    let mut file =
+        File::open(path).with_context(|| format!("Could not read {}", path.display()))?;
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let read = file
+            .read(&mut buffer)
+            .with_context(|| format!("Could not read {}", path.display()))?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(to_hex(&hasher.finalize()))
+}
+
+fn hash_bytes(bytes: &[u8]) -> String {
+    to_hex(&Sha256::digest(bytes))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
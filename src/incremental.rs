@@ -0,0 +1,106 @@
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use anyhow::{Context, Result, anyhow};
+
+/// Expands glob patterns into the files they match. Directories are replaced by the
+/// files they contain, recursively.
+///
+/// Each entry of the result holds the files matched by the pattern at the same position.
+pub fn expand_patterns(patterns: &[String], key: &str) -> Result<Vec<Vec<PathBuf>>> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            let paths = glob::glob(pattern)
+                .map_err(|e| anyhow!("Invalid pattern `{}` in `{}`: {}", pattern, key, e))?;
+            let mut files = vec![];
+            for path in paths {
+                let path = path.with_context(|| format!("Could not expand `{}`", pattern))?;
+                collect_files(&path, &mut files)?;
+            }
+            Ok(files)
+        })
+        .collect()
+}
+
+/// The files and directories matched by glob patterns, without expanding directories,
+/// sorted and without duplicates.
+pub fn matching_paths(patterns: &[String], key: &str) -> Result<Vec<PathBuf>> {
+    let mut matches = vec![];
+    for pattern in patterns {
+        let paths = glob::glob(pattern)
+            .map_err(|e| anyhow!("Invalid pattern `{}` in `{}`: {}", pattern, key, e))?;
+        for path in paths {
+            matches.push(path.with_context(|| format!("Could not expand `{}`", pattern))?);
+        }
+    }
+    matches.sort();
+    matches.dedup();
+    Ok(matches)
+}
+
+fn collect_files(path: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    if path.is_dir() {
+        let mut entries = std::fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.path()))
+            .collect::<std::io::Result<Vec<PathBuf>>>()?;
+        entries.sort();
+        for entry in entries {
+            collect_files(&entry, files)?;
+        }
+    } else {
+        files.push(path.to_path_buf());
+    }
+    Ok(())
+}
+
+fn modified(path: &Path) -> Result<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .with_context(|| format!("Could not read the modification time of {}", path.display()))
+}
+
+/// Whether every output pattern matches at least one file.
+pub fn outputs_exist(outputs: &[String]) -> Result<bool> {
+    Ok(missing_outputs(outputs)?.is_empty())
+}
+
+/// The output patterns that match no file.
+pub fn missing_outputs(outputs: &[String]) -> Result<Vec<String>> {
+    Ok(outputs
+        .iter()
+        .zip(expand_patterns(outputs, "outputs")?)
+        .filter(|(_, files)| files.is_empty())
+        .map(|(pattern, _)| pattern.clone())
+        .collect())
+}
+
+/// Whether a task's outputs are up to date: every output pattern matches at least one
+/// file, and every output file is newer than every source file.
+pub fn is_up_to_date(sources: &[String], outputs: &[String]) -> Result<bool> {
+    if outputs.is_empty() || !outputs_exist(outputs)? {
+        return Ok(false);
+    }
+    Ok(newer_sources(sources, outputs)?.is_empty())
+}
+
+/// The source files that are not older than every output file.
+pub fn newer_sources(sources: &[String], outputs: &[String]) -> Result<Vec<PathBuf>> {
+    let Some(oldest_output) = expand_patterns(outputs, "outputs")?
+        .iter()
+        .flatten()
+        .map(|path| modified(path))
+        .collect::<Result<Vec<SystemTime>>>()?
+        .into_iter()
+        .min()
+    else {
+        return Ok(vec![]);
+    };
+    let mut newer = vec![];
+    for source in expand_patterns(sources, "sources")?.into_iter().flatten() {
+        if modified(&source)? >= oldest_output {
+            newer.push(source);
+        }
+    }
+    Ok(newer)
+}
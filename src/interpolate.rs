@@ -0,0 +1,120 @@
+use std::collections::HashMap;
+
+/// Replaces every `{{name}}` placeholder (optionally written as `{{ name }}`) with its
+/// value from `values`.
+///
+/// Placeholders whose name is not in `values`, or that are not a plain name (such as
+/// Go templates like `{{.State.Running}}`), are left untouched.
+pub fn interpolate(text: &str, values: &HashMap<String, String>) -> String {
+    interpolate_escaped(text, values, str::to_string)
+}
+
+/// Like `interpolate`, with each value passed through `escape` before being substituted,
+/// so that it is taken literally where the text has a syntax of its own.
+pub fn interpolate_escaped(
+    text: &str,
+    values: &HashMap<String, String>,
+    escape: impl Fn(&str) -> String,
+) -> String {
+    replace_placeholders(text, |name| values.get(name).map(|value| escape(value)))
+}
+
+/// Names of the `{{name}}` placeholders in `text`, in order of appearance.
+pub fn placeholders(text: &str) -> Vec<String> {
+    let mut names = vec![];
+    replace_placeholders(text, |name| {
+        names.push(name.to_string());
+        None
+    });
+    names
+}
+
+fn replace_placeholders(text: &str, mut lookup: impl FnMut(&str) -> Option<String>) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        result.push_str(&rest[..start]);
+        let after_open = &rest[start + 2..];
+        let Some(end) = after_open.find("}}") else {
+            rest = &rest[start..];
+            break;
+        };
+        let name = after_open[..end].trim();
+        let value = if is_placeholder_name(name) {
+            lookup(name)
+        } else {
+            None
+        };
+        match value {
+            Some(value) => {
+                result.push_str(&value);
+                rest = &after_open[end + 2..];
+            }
+            None => {
+                // keep the braces and look for the next placeholder right after them
+                result.push_str("{{");
+                rest = after_open;
+            }
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+fn is_placeholder_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn values() -> HashMap<String, String> {
+        HashMap::from([
+            ("target".to_string(), "x86_64".to_string()),
+            ("profile".to_string(), "release".to_string()),
+        ])
+    }
+
+    #[test]
+    fn test_interpolate() {
+        assert_eq!(
+            interpolate("cargo build --target {{target}} --{{ profile }}", &values()),
+            "cargo build --target x86_64 --release"
+        );
+        assert_eq!(
+            interpolate("{{target}}{{target}}", &values()),
+            "x86_64x86_64"
+        );
+    }
+
+    #[test]
+    fn test_interpolate_leaves_unknown_placeholders() {
+        assert_eq!(
+            interpolate("docker inspect -f '{{.State.Running}}' {{name}}", &values()),
+            "docker inspect -f '{{.State.Running}}' {{name}}"
+        );
+        assert_eq!(interpolate("{{ {{target}}", &values()), "{{ x86_64");
+        assert_eq!(interpolate("echo {{target", &values()), "echo {{target");
+    }
+
+    #[test]
+    fn test_interpolate_escaped() {
+        let values = HashMap::from([("name".to_string(), "a [b]".to_string())]);
+        assert_eq!(
+            interpolate_escaped("out/{{name}}/*", &values, glob::Pattern::escape),
+            "out/a [[]b[]]/*"
+        );
+    }
+
+    #[test]
+    fn test_placeholders() {
+        assert_eq!(
+            placeholders("docker build -t {{image}}:{{ tag }} {{.Dir}} {{image}}"),
+            vec!["image", "tag", "image"]
+        );
+        assert!(placeholders("echo {{ tag").is_empty());
+    }
+}
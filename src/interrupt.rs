@@ -0,0 +1,48 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// How often a sleep checks whether the run has been interrupted.
+const SLEEP_STEP: Duration = Duration::from_millis(50);
+
+/// Number of times SIGINT has been received since the handler was installed.
+static INTERRUPTS: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    INTERRUPTS.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Catches Ctrl-C (SIGINT), so that jake is not killed along with the running commands:
+/// it stops starting new tasks instead, and still runs the cleanup tasks of the
+/// interrupted ones.
+pub fn install_handler() {
+    // SAFETY: the handler only performs an atomic increment, which is async-signal-safe
+    unsafe {
+        libc::signal(
+            libc::SIGINT,
+            on_interrupt as *const () as libc::sighandler_t,
+        )
+    };
+}
+
+/// Number of interrupts received so far, to tell whether a new one arrived since.
+pub fn interrupt_count() -> usize {
+    INTERRUPTS.load(Ordering::SeqCst)
+}
+
+/// Whether the run has been interrupted with Ctrl-C.
+pub fn interrupted() -> bool {
+    interrupt_count() > 0
+}
+
+/// Sleeps for `duration`, or until the run is interrupted with Ctrl-C.
+pub fn sleep_unless_interrupted(duration: Duration) {
+    let deadline = Instant::now() + duration;
+    while !interrupted() {
+        let now = Instant::now();
+        if now >= deadline {
+            break;
+        }
+        thread::sleep((deadline - now).min(SLEEP_STEP));
+    }
+}
@@ -1,13 +1,53 @@
 use std::collections::HashMap;
 use std::env;
-use std::path::Path;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::Duration;
 
-use crate::models::{Executor, NodeState, TaskNode};
-use anyhow::{Result, anyhow};
-use toml::map::Map;
+use crate::cache::{ArtifactCache, Fingerprint, FingerprintCache};
+use crate::env_vars::dotenv_to_hashmap;
+use crate::incremental::{
+    is_up_to_date, matching_paths, missing_outputs, newer_sources, outputs_exist,
+};
+use crate::interpolate::{interpolate, interpolate_escaped, placeholders};
+use crate::interrupt::{interrupted, sleep_unless_interrupted};
+use crate::models::{
+    BackgroundProcess, Executor, ExitError, NodeState, Readiness, ReadyCheck, RunOptions, TaskNode,
+    TaskOptions, TimeoutError,
+};
+use crate::scheduler::run_graph;
+use anyhow::{Context, Result, anyhow};
 use toml::{Table, Value};
 
 const JAKEFILE: &str = "jakefile.toml";
+const SETTINGS: &str = "settings";
+const VARS: &str = "vars";
+/// Top-level keys that configure the jakefile itself instead of defining a task.
+const RESERVED_KEYS: [&str; 2] = [SETTINGS, VARS];
+
+/// A parsed jakefile, along with the directory it lives in, its `[settings]` table
+/// and its `[vars]` table.
+struct Jakefile {
+    tasks: Table,
+    dir: PathBuf,
+    settings: Settings,
+    vars: HashMap<String, Var>,
+}
+
+/// A variable of the `[vars]` table.
+enum Var {
+    Value(String),
+    /// Command whose output is the variable's value, evaluated only when a task uses it.
+    Shell(String),
+}
+
+/// Jakefile-level defaults, applied to every task that does not override them.
+#[derive(Default)]
+struct Settings {
+    dir: Option<PathBuf>,
+    shell: Option<Vec<String>>,
+}
 
 pub fn is_posix_os() -> bool {
     let os_familiy = std::env::consts::FAMILY;
@@ -54,68 +94,781 @@ pub fn parse_jakefile(file_path: Option<&str>) -> Result<Table> {
     }
 }
 
+fn load_jakefile(file_path: Option<&str>) -> Result<Jakefile> {
+    let path = match file_path {
+        None => PathBuf::from(resolve_jakefile_path()?),
+        Some(p) => PathBuf::from(p),
+    };
+    let tasks = parse_jakefile(Some(&path.to_string_lossy()))?;
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let settings = parse_settings(&tasks)?;
+    let vars = parse_vars(&tasks)?;
+    Ok(Jakefile {
+        tasks,
+        dir,
+        settings,
+        vars,
+    })
+}
+
+fn parse_settings(table: &Table) -> Result<Settings> {
+    let mut settings = Settings::default();
+    let Some(value) = table.get(SETTINGS) else {
+        return Ok(settings);
+    };
+    let Some(settings_table) = value.as_table() else {
+        return Err(anyhow!("`{}` must be a table", SETTINGS));
+    };
+    for (key, value) in settings_table {
+        match key.as_str() {
+            "dir" => settings.dir = Some(PathBuf::from(string_from_value(value, "dir")?)),
+            "shell" => settings.shell = Some(shell_from_value(value)?),
+            _ => return Err(anyhow!("Unknown setting `{}` in `{}`", key, SETTINGS)),
+        }
+    }
+    Ok(settings)
+}
+
+/// Reads the `[vars]` table, whose values can be referenced as `{{name}}` by every task.
+/// A variable is either a plain value or a table such as `{ sh = "git rev-parse HEAD" }`.
+fn parse_vars(table: &Table) -> Result<HashMap<String, Var>> {
+    let Some(value) = table.get(VARS) else {
+        return Ok(HashMap::new());
+    };
+    let Some(vars_table) = value.as_table() else {
+        return Err(anyhow!("`{}` must be a table", VARS));
+    };
+    let mut vars = HashMap::new();
+    for (key, value) in vars_table {
+        let var = match (value.get("sh").and_then(Value::as_str), value) {
+            (Some(command), Value::Table(t)) if t.len() == 1 => Var::Shell(command.to_string()),
+            _ => Var::Value(scalar_to_string(value).ok_or_else(|| {
+                anyhow!(
+                    "Unsupported value for the variable `{}`: expected a string, a number, a boolean or a table such as {{ sh = \"<command>\" }}",
+                    key
+                )
+            })?),
+        };
+        vars.insert(key.clone(), var);
+    }
+    Ok(vars)
+}
+
+fn is_reserved(key: &str) -> bool {
+    RESERVED_KEYS.contains(&key)
+}
+
 pub fn list_jakefile_tasks(file_path: Option<&str>) -> Result<Vec<String>> {
     let parsed = parse_jakefile(file_path)?;
     let mut commands: Vec<String> = vec![];
 
-    for key in parsed.keys() {
+    for key in parsed.keys().filter(|k| !is_reserved(k)) {
         commands.push(key.clone().to_owned());
     }
 
     Ok(commands)
 }
 
-fn task_to_task_node(available_tasks: &Map<String, Value>, task: &str) -> Result<TaskNode> {
+/// A task to run, along with the inputs it is invoked with: either a task requested on
+/// the command line or an entry of a task's `depends_on` array.
+struct Invocation {
+    task: String,
+    /// Values of the task's parameters (for requested tasks, every `name=value`
+    /// assignment given on the command line).
+    params: HashMap<String, String>,
+    /// Extra arguments appended to the task's command.
+    args: Vec<String>,
+    /// The task whose `depends_on` array contains this invocation, if any.
+    dependent: Option<String>,
+}
+
+impl Invocation {
+    fn new(task: &str) -> Self {
+        Self {
+            task: task.to_string(),
+            params: HashMap::new(),
+            args: vec![],
+            dependent: None,
+        }
+    }
+}
+
+/// The tasks run after a task completes, each as a plan that includes its dependencies.
+struct Hooks {
+    /// Run when the task fails, before `finally`.
+    on_failure: Vec<Vec<TaskNode>>,
+    /// Run whether the task succeeds or fails.
+    finally: Vec<Vec<TaskNode>>,
+}
+
+impl Hooks {
+    fn nodes_mut(&mut self) -> impl Iterator<Item = &mut TaskNode> {
+        self.on_failure
+            .iter_mut()
+            .chain(self.finally.iter_mut())
+            .flatten()
+    }
+}
+
+/// A parameter declared in a task's `params` array.
+struct Param {
+    name: String,
+    default: Option<String>,
+    choices: Option<Vec<String>>,
+}
+
+fn task_to_task_node(
+    jakefile: &Jakefile,
+    task: &str,
+    params: &HashMap<String, String>,
+) -> Result<TaskNode> {
+    let available_tasks = &jakefile.tasks;
+    if is_reserved(task) {
+        return Err(anyhow!(
+            "`{}` is a reserved key of jakefile.toml and cannot be executed as a task",
+            task
+        ));
+    }
     if !available_tasks.contains_key(task) {
         return Err(anyhow!(
             "Task {} does not exist. Please define it within you jakefile.toml file",
             task
         ));
     }
-    let task_node = if let Some(task_table) = available_tasks[task].as_table() {
-        if !task_table.contains_key("command") {
+    let mut task_node = if let Some(task_table) = available_tasks[task].as_table() {
+        if task_table.contains_key("command") && task_table.contains_key("script") {
+            return Err(anyhow!(
+                "Task {} defines both `command` and `script`: only one of them can be used",
+                task
+            ));
+        }
+        let is_script = task_table.contains_key("script");
+        if !task_table.contains_key("command") && !is_script {
             return Err(anyhow!(
                 "`command` key not available for the requested task: ensure that there are no typos and the TOML syntax is correct before running again"
             ));
         }
-        let mut dependencies: Vec<String> = vec![];
-        if task_table.contains_key("depends_on") {
-            if let Some(depends) = task_table["depends_on"].as_array() {
-                for value in depends {
-                    match value.as_str() {
-                        Some(c) => dependencies.push(c.to_string()),
-                        None => continue,
-                    }
+        let command = if is_script {
+            string_from_value(&task_table["script"], "script")?
+        } else {
+            match task_table["command"].as_str() {
+                Some(c) => c,
+                None => return Err(anyhow!("Unsupported value for the task's command")),
+            }
+        };
+        let mut task_node = TaskNode::new(task.to_string(), command.to_string(), vec![]);
+        if is_script {
+            task_node.options.interpreter = Some(script_interpreter(task, task_table, command)?);
+        } else if task_table.contains_key("interpreter") {
+            return Err(anyhow!(
+                "Task {} sets an `interpreter` but has no `script`",
+                task
+            ));
+        }
+        if let Some(timeout) = task_table.get("timeout") {
+            task_node.options.timeout = Some(duration_from_value(timeout, "timeout")?);
+        }
+        if let Some(retries) = task_table.get("retries") {
+            task_node.retries = match retries.as_integer() {
+                Some(r) if r >= 0 => r as u32,
+                _ => {
+                    return Err(anyhow!(
+                        "Unsupported value for `retries`: expected a non-negative integer"
+                    ));
+                }
+            };
+        }
+        if let Some(retry_delay) = task_table.get("retry_delay") {
+            task_node.retry_delay = duration_from_value(retry_delay, "retry_delay")?;
+        }
+        if let Some(dir) = task_table.get("dir") {
+            task_node.options.dir = Some(PathBuf::from(string_from_value(dir, "dir")?));
+        }
+        if let Some(env) = task_table.get("env") {
+            task_node.options.env = env_from_value(env)?;
+        }
+        if let Some(shell) = task_table.get("shell") {
+            task_node.options.shell = Some(shell_from_value(shell)?);
+        }
+        match task_table.get("background") {
+            Some(Value::Boolean(true)) => {
+                if let Some(key) = ["timeout", "retries"]
+                    .into_iter()
+                    .find(|key| task_table.contains_key(*key))
+                {
+                    return Err(anyhow!(
+                        "Task {} runs in the background and cannot set `{}`",
+                        task,
+                        key
+                    ));
                 }
+                task_node.background = Some(readiness_from_value(
+                    jakefile,
+                    task_table.get("ready_when"),
+                )?);
+            }
+            Some(Value::Boolean(false)) | None => {
+                if task_table.contains_key("ready_when") {
+                    return Err(anyhow!(
+                        "Task {} sets `ready_when` but does not run in the `background`",
+                        task
+                    ));
+                }
+            }
+            Some(_) => {
+                return Err(anyhow!(
+                    "Unsupported value for `background`: expected a boolean"
+                ));
             }
         }
-        let command = match task_table["command"].as_str() {
-            Some(c) => c,
-            None => return Err(anyhow!("Unsupported value for the task's command")),
-        };
-        TaskNode::new(command.to_string(), dependencies)
+        if let Some(sources) = task_table.get("sources") {
+            task_node.sources = patterns_from_value(sources, "sources")?;
+        }
+        if let Some(outputs) = task_table.get("outputs") {
+            task_node.outputs = patterns_from_value(outputs, "outputs")?;
+        }
+        if let Some(run_after) = task_table.get("run_after") {
+            task_node.run_after = run_after_from_value(jakefile, task, run_after)?;
+        }
+        if let Some(declared) = task_table.get("params") {
+            let declared = params_from_value(declared)?;
+            task_node.params = resolve_params(task, &declared, params)?;
+        }
+        task_node
     } else {
         let command = match available_tasks[task].as_str() {
             Some(t) => t,
             None => return Err(anyhow!("Unsupported value for the task's command")),
         };
         let dependencies: Vec<String> = vec![];
-        TaskNode::new(command.to_string(), dependencies)
+        TaskNode::new(task.to_string(), command.to_string(), dependencies)
     };
+    if task_node.options.dir.is_none() {
+        task_node.options.dir = jakefile.settings.dir.clone();
+    }
+    if task_node.options.shell.is_none() {
+        task_node.options.shell = jakefile.settings.shell.clone();
+    }
     Ok(task_node)
 }
 
-fn resolve_dependencies(
-    available_tasks: &Map<String, Value>,
+/// Reads an array of glob patterns, such as `sources = ["src/**/*.rs"]`.
+fn patterns_from_value(value: &Value, key: &str) -> Result<Vec<String>> {
+    value
+        .as_array()
+        .and_then(|a| {
+            a.iter()
+                .map(|v| v.as_str().map(str::to_string))
+                .collect::<Option<Vec<String>>>()
+        })
+        .ok_or_else(|| {
+            anyhow!(
+                "Unsupported value for `{}`: expected an array of paths or glob patterns",
+                key
+            )
+        })
+}
+
+/// Reads a task's `depends_on` array. Its entries are either a task name or a table
+/// such as `{ task = "deploy", options = "--verbose", params = { env = "staging" } }`.
+fn dependencies_of(jakefile: &Jakefile, task: &str) -> Result<Vec<Invocation>> {
+    invocations_of(jakefile, task, "depends_on")
+}
+
+/// Reads an array of task invocations, such as `depends_on` or `finally`.
+fn invocations_of(jakefile: &Jakefile, task: &str, key: &str) -> Result<Vec<Invocation>> {
+    let Some(value) = jakefile.tasks[task]
+        .as_table()
+        .and_then(|table| table.get(key))
+    else {
+        return Ok(vec![]);
+    };
+    let invalid = || {
+        anyhow!(
+            "Unsupported value for the `{}` of task {}: expected an array of task names or of tables with a `task` key",
+            key,
+            task
+        )
+    };
+    let mut invocations = vec![];
+    for entry in value.as_array().ok_or_else(invalid)? {
+        let invocation = match entry {
+            Value::String(name) => Invocation::new(name),
+            Value::Table(table) => {
+                let name = table
+                    .get("task")
+                    .and_then(Value::as_str)
+                    .ok_or_else(invalid)?;
+                let mut invocation = Invocation::new(name);
+                for (entry_key, value) in table {
+                    match entry_key.as_str() {
+                        "task" => {}
+                        "options" => {
+                            invocation.args = split_options(string_from_value(value, "options")?)?
+                        }
+                        "params" => {
+                            let params = value.as_table().ok_or_else(|| {
+                                anyhow!("Unsupported value for `params`: expected a table of parameter values")
+                            })?;
+                            for (param, value) in params {
+                                let value = scalar_to_string(value).ok_or_else(|| {
+                                    anyhow!(
+                                        "Unsupported value for the parameter `{}`: expected a string, a number or a boolean",
+                                        param
+                                    )
+                                })?;
+                                invocation.params.insert(param.clone(), value);
+                            }
+                        }
+                        _ => {
+                            return Err(anyhow!(
+                                "Unknown key `{}` in the `{}` of task {}",
+                                entry_key,
+                                key,
+                                task
+                            ));
+                        }
+                    }
+                }
+                invocation
+            }
+            _ => return Err(invalid()),
+        };
+        invocations.push(Invocation {
+            dependent: Some(task.to_string()),
+            ..invocation
+        });
+    }
+    Ok(invocations)
+}
+
+/// Reads the `ready_when` table of a background task, such as `{ port = 5432 }`,
+/// `{ file = "/tmp/ready" }` or `{ command = "pg_isready", timeout = "1m" }`.
+fn readiness_from_value(jakefile: &Jakefile, value: Option<&Value>) -> Result<Readiness> {
+    let mut readiness = Readiness {
+        check: None,
+        timeout: Readiness::DEFAULT_TIMEOUT,
+    };
+    let Some(value) = value else {
+        return Ok(readiness);
+    };
+    let invalid = || {
+        anyhow!(
+            "Unsupported value for `ready_when`: expected a table with one of `port`, `file` or `command`, and an optional `timeout`"
+        )
+    };
+    let table = value.as_table().ok_or_else(invalid)?;
+    for (key, value) in table {
+        let check = match key.as_str() {
+            "timeout" => {
+                readiness.timeout = duration_from_value(value, "timeout")?;
+                continue;
+            }
+            "port" => ReadyCheck::Port(
+                value
+                    .as_integer()
+                    .and_then(|port| u16::try_from(port).ok())
+                    .ok_or_else(|| {
+                        anyhow!("Unsupported value for `port`: expected a port number")
+                    })?,
+            ),
+            "file" => ReadyCheck::File(jakefile.dir.join(string_from_value(value, "file")?)),
+            "command" => ReadyCheck::Command(string_from_value(value, "command")?.to_string()),
+            _ => return Err(invalid()),
+        };
+        if readiness.check.replace(check).is_some() {
+            return Err(invalid());
+        }
+    }
+    if readiness.check.is_none() {
+        return Err(invalid());
+    }
+    Ok(readiness)
+}
+
+/// Reads a task's `run_after` array, the names of the tasks it must run after.
+fn run_after_from_value(jakefile: &Jakefile, task: &str, value: &Value) -> Result<Vec<String>> {
+    let names = value
+        .as_array()
+        .and_then(|a| {
+            a.iter()
+                .map(|v| v.as_str().map(str::to_string))
+                .collect::<Option<Vec<String>>>()
+        })
+        .ok_or_else(|| {
+            anyhow!("Unsupported value for `run_after`: expected an array of task names")
+        })?;
+    if let Some(name) = names
+        .iter()
+        .find(|name| is_reserved(name) || !jakefile.tasks.contains_key(name.as_str()))
+    {
+        return Err(anyhow!(
+            "Task {} must run after task {}, which does not exist",
+            task,
+            name
+        ));
+    }
+    Ok(names)
+}
+
+/// Moves every task after the tasks of the run it must run after, keeping the
+/// execution order otherwise.
+fn apply_run_after(execution_order: Vec<TaskNode>) -> Result<Vec<TaskNode>> {
+    let mut pending = execution_order;
+    let mut ordered: Vec<TaskNode> = vec![];
+    while !pending.is_empty() {
+        let next = pending.iter().position(|node| {
+            node.dependencies
+                .iter()
+                .all(|dep| ordered.iter().any(|done| &done.id == dep))
+                && !pending
+                    .iter()
+                    .any(|other| other.name != node.name && node.run_after.contains(&other.name))
+        });
+        match next {
+            Some(i) => ordered.push(pending.remove(i)),
+            None => {
+                let names: Vec<&str> = pending.iter().map(|node| node.id.as_str()).collect();
+                return Err(anyhow!(
+                    "Circular ordering detected between tasks {}: check their `depends_on` and `run_after`",
+                    names.join(", ")
+                ));
+            }
+        }
+    }
+    Ok(ordered)
+}
+
+/// Builds the identifier of a node from the task's name and the inputs it runs with.
+fn node_id(task_node: &TaskNode) -> String {
+    let mut id = task_node.name.clone();
+    for (name, value) in &task_node.params {
+        id.push_str(&format!(" {}={}", name, shell_words::quote(value)));
+    }
+    if !task_node.args.is_empty() {
+        id.push(' ');
+        id.push_str(&shell_words::join(&task_node.args));
+    }
+    id
+}
+
+/// Reads a task's `params` array, whose entries are either a parameter name or a
+/// table such as `{ name = "profile", default = "debug", choices = ["debug", "release"] }`.
+fn params_from_value(value: &Value) -> Result<Vec<Param>> {
+    let invalid = || {
+        anyhow!(
+            "Unsupported value for `params`: expected an array of parameter names or of tables with a `name` key"
+        )
+    };
+    let array = value.as_array().ok_or_else(invalid)?;
+    let mut params = vec![];
+    for entry in array {
+        let param = match entry {
+            Value::String(name) => Param {
+                name: name.clone(),
+                default: None,
+                choices: None,
+            },
+            Value::Table(table) => {
+                let name = table
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .ok_or_else(invalid)?;
+                let default = match table.get("default") {
+                    Some(default) => Some(string_from_value(default, "default")?.to_string()),
+                    None => None,
+                };
+                let choices = match table.get("choices") {
+                    Some(choices) => Some(
+                        choices
+                            .as_array()
+                            .and_then(|a| {
+                                a.iter()
+                                    .map(|v| v.as_str().map(str::to_string))
+                                    .collect::<Option<Vec<String>>>()
+                            })
+                            .ok_or_else(|| {
+                                anyhow!(
+                                    "Unsupported value for `choices`: expected an array of strings"
+                                )
+                            })?,
+                    ),
+                    None => None,
+                };
+                Param {
+                    name: name.to_string(),
+                    default,
+                    choices,
+                }
+            }
+            _ => return Err(invalid()),
+        };
+        params.push(param);
+    }
+    Ok(params)
+}
+
+/// Matches the values given on the command line against the parameters declared by
+/// a task, falling back to their defaults and validating their choices.
+fn resolve_params(
     task: &str,
-    execution_order: &mut Vec<String>,
+    declared: &[Param],
+    given: &HashMap<String, String>,
+) -> Result<Vec<(String, String)>> {
+    let mut resolved = vec![];
+    for param in declared {
+        let value = match (given.get(&param.name), &param.default) {
+            (Some(value), _) => value.clone(),
+            (None, Some(default)) => default.clone(),
+            (None, None) => {
+                return Err(anyhow!(
+                    "Task {} requires the parameter `{}`: pass it as `{}=<value>`",
+                    task,
+                    param.name,
+                    param.name
+                ));
+            }
+        };
+        if let Some(choices) = &param.choices
+            && !choices.contains(&value)
+        {
+            return Err(anyhow!(
+                "Invalid value `{}` for the parameter `{}` of task {}: expected one of {}",
+                value,
+                param.name,
+                task,
+                choices.join(", ")
+            ));
+        }
+        resolved.push((param.name.clone(), value));
+    }
+    Ok(resolved)
+}
+
+/// Substitutes `{{name}}` placeholders in a task's command, working directory,
+/// environment variables, sources and outputs, then resolves the working directory
+/// and the patterns against the directory containing jakefile.toml.
+fn interpolate_node(
+    task_node: &mut TaskNode,
+    values: &HashMap<String, String>,
+    jakefile_dir: &Path,
+) {
+    // values are shell-quoted in commands, so that a value passed on the command line
+    // cannot inject more commands; script bodies are not shell code and take them as-is
+    task_node.command = if task_node.options.interpreter.is_some() {
+        interpolate(&task_node.command, values)
+    } else {
+        interpolate_escaped(&task_node.command, values, |value| {
+            shell_words::quote(value).into_owned()
+        })
+    };
+    if let Some(dir) = &task_node.options.dir {
+        let dir = interpolate(&dir.to_string_lossy(), values);
+        task_node.options.dir = Some(jakefile_dir.join(dir));
+    }
+    for (_, value) in task_node.options.env.iter_mut() {
+        *value = interpolate(value, values);
+    }
+    // sources and outputs are relative to the task's working directory, if any, and
+    // substituted values are matched literally
+    let base = task_node.options.dir.as_deref().unwrap_or(jakefile_dir);
+    let base = glob::Pattern::escape(&base.to_string_lossy());
+    for pattern in task_node
+        .sources
+        .iter_mut()
+        .chain(task_node.outputs.iter_mut())
+    {
+        let interpolated = interpolate_escaped(pattern, values, glob::Pattern::escape);
+        *pattern = if Path::new(&interpolated).is_absolute() {
+            interpolated
+        } else {
+            format!("{}/{}", base, interpolated)
+        };
+    }
+}
+
+/// Reads a task's `env` table. Numbers and booleans are accepted and converted to
+/// strings, so that `PORT = 8080` does not need to be quoted.
+fn env_from_value(value: &Value) -> Result<Vec<(String, String)>> {
+    let Some(table) = value.as_table() else {
+        return Err(anyhow!(
+            "Unsupported value for `env`: expected a table of environment variables"
+        ));
+    };
+    let mut env = vec![];
+    for (key, value) in table {
+        let value = scalar_to_string(value).ok_or_else(|| {
+            anyhow!(
+                "Unsupported value for environment variable `{}`: expected a string, a number or a boolean",
+                key
+            )
+        })?;
+        env.push((key.clone(), value));
+    }
+    Ok(env)
+}
+
+fn scalar_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Integer(i) => Some(i.to_string()),
+        Value::Float(f) => Some(f.to_string()),
+        Value::Boolean(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// Reads a `shell` setting, e.g. `["bash", "-euo", "pipefail", "-c"]`: the program
+/// followed by the arguments that come before the command.
+fn shell_from_value(value: &Value) -> Result<Vec<String>> {
+    let invalid = || {
+        anyhow!(
+            "Unsupported value for `shell`: expected a non-empty array of strings, such as [\"bash\", \"-c\"]"
+        )
+    };
+    let array = value.as_array().ok_or_else(invalid)?;
+    let shell = array
+        .iter()
+        .map(|v| v.as_str().map(str::to_string))
+        .collect::<Option<Vec<String>>>()
+        .ok_or_else(invalid)?;
+    if shell.is_empty() {
+        return Err(invalid());
+    }
+    Ok(shell)
+}
+
+/// Finds the interpreter of a script recipe: the `interpreter` key, or else the
+/// script's shebang line (`#!/usr/bin/env python3`).
+fn script_interpreter(task: &str, task_table: &Table, script: &str) -> Result<Vec<String>> {
+    if let Some(interpreter) = task_table.get("interpreter") {
+        let interpreter: Vec<String> = match interpreter {
+            Value::String(s) => s.split_whitespace().map(str::to_string).collect(),
+            Value::Array(a) => a
+                .iter()
+                .map(|v| v.as_str().map(str::to_string))
+                .collect::<Option<Vec<String>>>()
+                .unwrap_or_default(),
+            _ => vec![],
+        };
+        if interpreter.is_empty() {
+            return Err(anyhow!(
+                "Unsupported value for `interpreter`: expected a program name, such as \"python3\""
+            ));
+        }
+        return Ok(interpreter);
+    }
+    // like the kernel does, everything after the interpreter path is a single argument
+    let shebang = script.trim_start().lines().next().unwrap_or_default();
+    if let Some(line) = shebang.strip_prefix("#!") {
+        let line = line.trim();
+        if !line.is_empty() {
+            let mut interpreter = vec![];
+            match line.split_once(char::is_whitespace) {
+                Some((program, argument)) => {
+                    interpreter.push(program.to_string());
+                    interpreter.push(argument.trim().to_string());
+                }
+                None => interpreter.push(line.to_string()),
+            }
+            return Ok(interpreter);
+        }
+    }
+    Err(anyhow!(
+        "Task {} defines a `script` without an `interpreter` or a shebang line",
+        task
+    ))
+}
+
+fn string_from_value<'a>(value: &'a Value, key: &str) -> Result<&'a str> {
+    value
+        .as_str()
+        .ok_or_else(|| anyhow!("Unsupported value for `{}`: expected a string", key))
+}
+
+/// Parses a duration such as `"30s"`, `"5m"`, `"1h30m"` or `"500ms"`.
+/// A plain number is interpreted as a number of seconds.
+fn parse_duration(value: &str) -> Result<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Ok(Duration::from_secs(seconds));
+    }
+    let invalid = || {
+        anyhow!(
+            "Invalid duration `{}`: expected a number followed by a unit (ms, s, m or h), e.g. \"5m\"",
+            value
+        )
+    };
+    let mut total = Duration::ZERO;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let amount: u64 = rest[..digits].parse().map_err(|_| invalid())?;
+        rest = &rest[digits..];
+        let unit_len = rest
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        total += match &rest[..unit_len] {
+            "ms" => Duration::from_millis(amount),
+            "s" => Duration::from_secs(amount),
+            "m" => Duration::from_secs(amount * 60),
+            "h" => Duration::from_secs(amount * 3600),
+            _ => return Err(invalid()),
+        };
+        rest = &rest[unit_len..];
+    }
+    if total.is_zero() {
+        return Err(invalid());
+    }
+    Ok(total)
+}
+
+fn duration_from_value(value: &Value, key: &str) -> Result<Duration> {
+    match value {
+        Value::String(s) => parse_duration(s),
+        Value::Integer(i) if *i > 0 => Ok(Duration::from_secs(*i as u64)),
+        _ => Err(anyhow!(
+            "Unsupported value for `{}`: expected a duration such as \"30s\" or \"5m\"",
+            key
+        )),
+    }
+}
+
+/// Adds the node of `invocation` to `execution_order`, after its dependencies, and
+/// returns its identifier.
+fn resolve_dependencies(
+    jakefile: &Jakefile,
+    invocation: &Invocation,
+    execution_order: &mut Vec<TaskNode>,
     state_map: &mut HashMap<String, NodeState>,
-) -> Result<()> {
-    let task_node = task_to_task_node(available_tasks, task)?;
-    if let Some(current_state) = state_map.get(task) {
+) -> Result<String> {
+    let task = invocation.task.as_str();
+    let mut task_node = task_to_task_node(jakefile, task, &invocation.params)?;
+    if let Some(dependent) = &invocation.dependent
+        && let Some(name) = invocation
+            .params
+            .keys()
+            .find(|name| !task_node.params.iter().any(|(param, _)| param == *name))
+    {
+        return Err(anyhow!(
+            "Task {} passes the parameter `{}` to task {}, which does not declare it",
+            dependent,
+            name,
+            task
+        ));
+    }
+    task_node.args = invocation.args.clone();
+    task_node.id = node_id(&task_node);
+    let id = task_node.id.clone();
+    if let Some(current_state) = state_map.get(&id) {
         match current_state {
             NodeState::Visited => {
-                return Ok(());
+                return Ok(id);
             }
             NodeState::Visiting => {
                 return Err(anyhow!(
@@ -126,108 +879,739 @@ fn resolve_dependencies(
             NodeState::Univisited => {}
         }
     } else {
-        state_map.insert(task.to_string(), NodeState::Univisited);
+        state_map.insert(id.clone(), NodeState::Univisited);
     }
 
     state_map
-        .entry(task.to_string())
+        .entry(id.clone())
         .and_modify(|v| *v = NodeState::Visiting)
         .or_insert(NodeState::Visiting);
 
-    for dep in task_node.dependencies {
-        resolve_dependencies(available_tasks, &dep, execution_order, state_map)?;
+    // parameters given on the command line only apply to the requested tasks
+    for dependency in dependencies_of(jakefile, task)? {
+        let dependency_id =
+            resolve_dependencies(jakefile, &dependency, execution_order, state_map)?;
+        if !task_node.dependencies.contains(&dependency_id) {
+            task_node.dependencies.push(dependency_id);
+        }
     }
 
     state_map
-        .entry(task.to_string())
+        .entry(id.clone())
         .and_modify(|v| *v = NodeState::Visited)
         .or_insert(NodeState::Visited);
 
-    execution_order.push(task_node.command);
+    execution_order.push(task_node);
 
-    Ok(())
+    Ok(id)
 }
 
-pub fn execute_command(
+/// Executes several tasks as a single graph, so that the dependencies they share
+/// run only once. When running serially, tasks run in the order they are given.
+///
+/// `params` holds the `name=value` assignments given on the command line. They set the
+/// parameters declared by the requested tasks, or override the jakefile's `[vars]`,
+/// and are validated before anything runs.
+pub fn execute_commands(
     jakefile_path: Option<&str>,
-    task: &str,
-    flags: &str,
+    tasks: &[String],
+    params: &HashMap<String, String>,
+    args: &[String],
     executor: &dyn Executor,
-    load_env: bool,
+    run_options: &RunOptions,
 ) -> Result<()> {
-    if task.is_empty() {
-        return Ok(());
-    }
-    let cmd_options: Vec<&str> = if flags.is_empty() {
-        vec![]
+    let jakefile = load_jakefile(jakefile_path)?;
+    let Plan {
+        nodes: execution_order,
+        hooks,
+    } = resolve_plan(&jakefile, tasks, params, args)?;
+    // loaded once, before tasks run in parallel, since loading it modifies the process
+    // environment
+    let dotenv = if run_options.load_env {
+        dotenv_to_hashmap()?
     } else {
-        flags.split_whitespace().collect()
+        HashMap::new()
     };
-    let available_tasks = parse_jakefile(jakefile_path)?;
-    let mut execution_order: Vec<String> = vec![];
-    let mut state_map: HashMap<String, NodeState> = HashMap::new();
-    resolve_dependencies(&available_tasks, task, &mut execution_order, &mut state_map)?;
-    for command in &execution_order[..execution_order.len() - 1] {
-        let command_slice: Vec<&str> = command.split_whitespace().collect();
-        let command_args = if command_slice.len() > 1 {
-            &command_slice[1..]
-        } else {
-            &[]
-        };
-        executor.execute(command_slice[0], command_args.to_vec(), load_env)?;
-    }
-    // the actual command to execute is the last one in the execution order
-    let cmd = &execution_order[execution_order.len() - 1];
-    let cmd_parts: Vec<&str> = cmd.split_whitespace().collect();
-    let main_command = cmd_parts[0];
-    if cmd_parts.len() == 1 && cmd_options.is_empty() {
-        executor.execute(main_command, vec![], load_env)?;
-    } else if cmd_parts.len() == 1 && !cmd_options.is_empty() {
-        executor.execute(main_command, cmd_options, load_env)?;
-    } else if cmd_parts.len() > 1 && cmd_options.is_empty() {
-        let cmd_slice = &cmd_parts[1..];
-        executor.execute(main_command, cmd_slice.to_vec(), load_env)?;
-    } else {
-        let cmd_slice = [&cmd_parts[1..], &cmd_options[..]].concat();
-        executor.execute(main_command, cmd_slice, load_env)?;
+    let background = Mutex::new(vec![]);
+    let cache = FingerprintCache::new(&jakefile.dir);
+    let artifacts = ArtifactCache::from_env(&jakefile.dir);
+    let result = run_graph(&execution_order, run_options, |task_node| {
+        if let Some(readiness) = &task_node.background {
+            let result = start_background(task_node, readiness, executor, &dotenv);
+            match result {
+                // its `finally` tasks run once it has been stopped
+                Ok(process) => {
+                    background
+                        .lock()
+                        .unwrap()
+                        .push((task_node.id.clone(), process));
+                    return Ok(());
+                }
+                Err(e) => {
+                    let hooks_result = run_hooks(&hooks[&task_node.id], false, executor, &dotenv);
+                    return first_error(Err(e), hooks_result);
+                }
+            }
+        }
+        let fingerprint = fingerprint_of(task_node)?;
+        let decision = decide(
+            task_node,
+            fingerprint.as_ref(),
+            &cache,
+            &artifacts,
+            run_options,
+        )?;
+        if decision == Decision::UpToDate {
+            eprintln!("Task {} is up to date", task_node.id);
+            return Ok(());
+        }
+        // the outputs may have left the cache since the decision
+        if let (Decision::Restore, Some(fingerprint)) = (&decision, &fingerprint)
+            && artifacts.restore(fingerprint, &task_node.outputs)?
+        {
+            eprintln!("Task {} restored from the cache", task_node.id);
+            cache.store(task_node, fingerprint)?;
+            return Ok(());
+        }
+        // failing to record the run fails the task, whose hooks still run
+        let result = run_task_node(task_node, executor, &dotenv).and_then(|()| {
+            if let Some(fingerprint) = &fingerprint
+                && !run_options.dry_run
+            {
+                cache.store(task_node, fingerprint)?;
+                if !task_node.outputs.is_empty() {
+                    artifacts.store(fingerprint, &task_node.outputs)?;
+                }
+            }
+            Ok(())
+        });
+        let hooks_result = run_hooks(&hooks[&task_node.id], result.is_ok(), executor, &dotenv);
+        first_error(result, hooks_result)
+    });
+    // background tasks are stopped once every other task has completed, the most
+    // recently started first
+    let background = background.into_inner().unwrap();
+    background
+        .into_iter()
+        .rev()
+        .fold(result, |result, (id, process)| {
+            drop(process);
+            let hooks_result = run_hooks(&hooks[&id], true, executor, &dotenv);
+            first_error(result, hooks_result)
+        })
+}
+
+/// Whether a task can be skipped. When the fingerprint of its last successful run is
+/// recorded, it decides on its own: the task is skipped only if its fingerprint still
+/// matches and its outputs, if any, are still there. Otherwise, its outputs must be
+/// newer than its sources.
+fn is_fresh(
+    cache: &FingerprintCache,
+    task_node: &TaskNode,
+    fingerprint: Option<&Fingerprint>,
+) -> Result<bool> {
+    if let Some(fingerprint) = fingerprint
+        && let Some(last) = cache.load(task_node)
+    {
+        return Ok(last.hash == fingerprint.hash && outputs_exist(&task_node.outputs)?);
     }
-    Ok(())
+    is_up_to_date(&task_node.sources, &task_node.outputs)
 }
 
-pub fn execute_default_command(
-    jakefile_path: Option<&str>,
-    flags: &str,
-    executor: &dyn Executor,
-    load_env: bool,
-) -> Result<()> {
-    let available_tasks = parse_jakefile(jakefile_path)?;
-    if available_tasks.contains_key("default") {
-        execute_command(jakefile_path, "default", flags, executor, load_env)?;
-    } else {
-        let first_key = available_tasks.keys().next();
-        match first_key {
-            None => return Err(anyhow!("could not find any task within jakefile")),
-            Some(task) => {
-                execute_command(jakefile_path, task, flags, executor, load_env)?;
-            }
-        }
+/// The fingerprint of a task, if it has `sources`. Only such tasks are fingerprinted:
+/// without them, a task could depend on anything.
+fn fingerprint_of(task_node: &TaskNode) -> Result<Option<Fingerprint>> {
+    if task_node.sources.is_empty() {
+        return Ok(None);
     }
-    Ok(())
+    Ok(Some(Fingerprint::of(task_node)?))
 }
 
-#[cfg(test)]
-mod tests {
-    use serial_test::serial;
+/// What running a node does.
+#[derive(Debug, PartialEq)]
+enum Decision {
+    Run,
+    UpToDate,
+    /// Its outputs are restored from the artifact cache instead.
+    Restore,
+}
 
-    use crate::models::{CommandExecutor, DryRunExecutor};
+/// Decides whether a node runs, is skipped as up to date, or has its outputs restored
+/// from the artifact cache, both when running tasks and when explaining a run.
+fn decide(
+    task_node: &TaskNode,
+    fingerprint: Option<&Fingerprint>,
+    cache: &FingerprintCache,
+    artifacts: &ArtifactCache,
+    run_options: &RunOptions,
+) -> Result<Decision> {
+    if task_node.background.is_some() || run_options.force {
+        return Ok(Decision::Run);
+    }
+    if is_fresh(cache, task_node, fingerprint)? {
+        return Ok(Decision::UpToDate);
+    }
+    // dry runs leave the outputs as they are
+    if let Some(fingerprint) = fingerprint
+        && !task_node.outputs.is_empty()
+        && !run_options.dry_run
+        && artifacts.contains(fingerprint)
+    {
+        return Ok(Decision::Restore);
+    }
+    Ok(Decision::Run)
+}
 
-    use super::*;
+/// Why a node runs or is skipped, as printed by `--explain`.
+struct Explanation {
+    decision: Decision,
+    reasons: Vec<String>,
+}
 
-    struct MockCommandExecutor;
+fn explain_node(
+    task_node: &TaskNode,
+    jakefile_dir: &Path,
+    cache: &FingerprintCache,
+    artifacts: &ArtifactCache,
+    run_options: &RunOptions,
+) -> Result<Explanation> {
+    let fingerprint = fingerprint_of(task_node)?;
+    let decision = decide(
+        task_node,
+        fingerprint.as_ref(),
+        cache,
+        artifacts,
+        run_options,
+    )?;
+    let last = fingerprint.as_ref().and_then(|_| cache.load(task_node));
+    let reasons = match decision {
+        Decision::UpToDate if last.is_some() => {
+            vec!["nothing changed since its last successful run".to_string()]
+        }
+        Decision::UpToDate if task_node.sources.is_empty() => {
+            vec!["its outputs exist and it declares no `sources`".to_string()]
+        }
+        Decision::UpToDate => vec!["its outputs are newer than its sources".to_string()],
+        Decision::Restore => {
+            vec!["its outputs for the current inputs are in the cache".to_string()]
+        }
+        Decision::Run => run_reasons(task_node, jakefile_dir, fingerprint, last, run_options)?,
+    };
+    Ok(Explanation { decision, reasons })
+}
 
-    impl MockCommandExecutor {
-        pub fn new() -> Self {
-            Self {}
+/// Why a node runs, given the fingerprint of its last successful run, if any.
+fn run_reasons(
+    task_node: &TaskNode,
+    jakefile_dir: &Path,
+    fingerprint: Option<Fingerprint>,
+    last: Option<Fingerprint>,
+    run_options: &RunOptions,
+) -> Result<Vec<String>> {
+    if task_node.background.is_some() {
+        return Ok(vec!["it runs in the background".to_string()]);
+    }
+    if run_options.force {
+        return Ok(vec!["it is forced with `--force`".to_string()]);
+    }
+    if task_node.sources.is_empty() && task_node.outputs.is_empty() {
+        return Ok(vec![
+            "no caching configured: it declares neither `sources` nor `outputs`".to_string(),
+        ]);
+    }
+    let relative = |path: &str| {
+        let path = Path::new(path);
+        path.strip_prefix(jakefile_dir)
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    };
+    let mut reasons = vec![];
+    let missing = missing_outputs(&task_node.outputs)?;
+    for pattern in &missing {
+        reasons.push(format!("output `{}` is missing", relative(pattern)));
+    }
+    let Some(fingerprint) = fingerprint else {
+        return Ok(reasons);
+    };
+    let Some(last) = last else {
+        reasons.push("no successful run has been recorded".to_string());
+        if !task_node.outputs.is_empty() && missing.is_empty() {
+            for source in newer_sources(&task_node.sources, &task_node.outputs)? {
+                reasons.push(format!(
+                    "source `{}` is newer than the outputs",
+                    relative(&source.to_string_lossy())
+                ));
+            }
+        }
+        return Ok(reasons);
+    };
+    if last.command != fingerprint.command {
+        reasons.push("its command changed since the last run".to_string());
+    }
+    for (path, hash) in &fingerprint.sources {
+        match last.sources.get(path) {
+            None => reasons.push(format!("source `{}` was added", relative(path))),
+            Some(last_hash) if last_hash != hash => reasons.push(format!(
+                "source `{}` changed since the last run",
+                relative(path)
+            )),
+            Some(_) => {}
+        }
+    }
+    for path in last.sources.keys() {
+        if !fingerprint.sources.contains_key(path) {
+            reasons.push(format!("source `{}` was removed", relative(path)));
+        }
+    }
+    if reasons.is_empty() {
+        reasons.push(
+            "its arguments, parameters, environment or settings changed since the last run"
+                .to_string(),
+        );
+    }
+    Ok(reasons)
+}
+
+/// The nodes of a run, in a valid serial execution order, along with the `on_failure`
+/// and `finally` tasks of each of them, by node identifier.
+struct Plan {
+    nodes: Vec<TaskNode>,
+    hooks: HashMap<String, Hooks>,
+}
+
+/// Resolves the requested tasks and their dependencies into a plan, validating the
+/// parameters given on the command line and substituting parameters and variables.
+fn resolve_plan(
+    jakefile: &Jakefile,
+    tasks: &[String],
+    params: &HashMap<String, String>,
+    args: &[String],
+) -> Result<Plan> {
+    if tasks.len() > 1 && !args.is_empty() {
+        return Err(anyhow!(
+            "Extra options can only be forwarded when a single task is executed"
+        ));
+    }
+    let mut execution_order: Vec<TaskNode> = vec![];
+    let mut state_map: HashMap<String, NodeState> = HashMap::new();
+    for task in tasks {
+        let invocation = Invocation {
+            params: params.clone(),
+            args: args.to_vec(),
+            ..Invocation::new(task)
+        };
+        resolve_dependencies(jakefile, &invocation, &mut execution_order, &mut state_map)?;
+    }
+    for name in params.keys() {
+        let declared = execution_order
+            .iter()
+            .filter(|node| tasks.contains(&node.name))
+            .any(|node| node.params.iter().any(|(param, _)| param == name));
+        if !declared && !jakefile.vars.contains_key(name) {
+            return Err(anyhow!(
+                "Unknown parameter `{}`: it is neither declared by the requested tasks nor defined in `{}`",
+                name,
+                VARS
+            ));
+        }
+    }
+    let mut execution_order = apply_run_after(execution_order)?;
+    let mut hooks: HashMap<String, Hooks> = HashMap::new();
+    for task_node in &execution_order {
+        hooks.insert(
+            task_node.id.clone(),
+            resolve_hooks(jakefile, &task_node.name)?,
+        );
+    }
+    let mut nodes: Vec<&mut TaskNode> = execution_order.iter_mut().collect();
+    nodes.extend(hooks.values_mut().flat_map(Hooks::nodes_mut));
+    let vars = resolve_vars(jakefile, params, &nodes)?;
+    // a task's own parameters take precedence over the variables with the same name
+    for task_node in nodes {
+        let mut values = vars.clone();
+        values.extend(task_node.params.iter().cloned());
+        interpolate_node(task_node, &values, &jakefile.dir);
+    }
+    // a background task that no other task depends on, such as `jake serve`, simply
+    // runs in the foreground
+    let depended_on: Vec<String> = execution_order
+        .iter()
+        .flat_map(|task_node| task_node.dependencies.clone())
+        .collect();
+    for task_node in execution_order.iter_mut() {
+        if !depended_on.contains(&task_node.id) {
+            task_node.background = None;
+        }
+    }
+    Ok(Plan {
+        nodes: execution_order,
+        hooks,
+    })
+}
+
+/// Keeps the first of two errors, printing the other one.
+fn first_error(result: Result<()>, other: Result<()>) -> Result<()> {
+    match (result, other) {
+        (Err(e), Err(other)) => {
+            eprintln!("Error: {:#}", other);
+            Err(e)
+        }
+        (result, other) => result.and(other),
+    }
+}
+
+/// Starts a background task and waits until it is ready.
+fn start_background(
+    task_node: &TaskNode,
+    readiness: &Readiness,
+    executor: &dyn Executor,
+    dotenv: &HashMap<String, String>,
+) -> Result<BackgroundProcess> {
+    if task_node.command.trim().is_empty() {
+        return Err(anyhow!("Task {} has an empty command", task_node.name));
+    }
+    executor
+        .start(
+            &task_node.command,
+            task_node.args.iter().map(String::as_str).collect(),
+            dotenv,
+            &task_node.options,
+            readiness,
+        )
+        .with_context(|| format!("Background task {} failed to start", task_node.name))
+}
+
+/// Resolves the `on_failure` and `finally` tasks of a task, along with their dependencies.
+fn resolve_hooks(jakefile: &Jakefile, task: &str) -> Result<Hooks> {
+    let resolve = |key: &str| -> Result<Vec<Vec<TaskNode>>> {
+        let mut plans = vec![];
+        for invocation in invocations_of(jakefile, task, key)? {
+            let mut plan = vec![];
+            resolve_dependencies(jakefile, &invocation, &mut plan, &mut HashMap::new())?;
+            plans.push(plan);
+        }
+        Ok(plans)
+    };
+    Ok(Hooks {
+        on_failure: resolve("on_failure")?,
+        finally: resolve("finally")?,
+    })
+}
+
+/// Runs the `on_failure` tasks of a task that failed, then its `finally` tasks. They
+/// run even after Ctrl-C, and even when one of them fails: the first error is returned
+/// and the others are printed.
+fn run_hooks(
+    hooks: &Hooks,
+    succeeded: bool,
+    executor: &dyn Executor,
+    dotenv: &HashMap<String, String>,
+) -> Result<()> {
+    let on_failure = if succeeded {
+        &[][..]
+    } else {
+        &hooks.on_failure
+    };
+    let mut result = Ok(());
+    for plan in on_failure.iter().chain(&hooks.finally) {
+        let plan_result = plan
+            .iter()
+            .try_for_each(|task_node| run_task_node(task_node, executor, dotenv));
+        result = first_error(result, plan_result);
+    }
+    result
+}
+
+/// Computes the values of the jakefile's variables, applying the overrides given on
+/// the command line. Variables defined by a command are only evaluated when one of the
+/// tasks that may run, `on_failure` and `finally` tasks included, references them.
+fn resolve_vars(
+    jakefile: &Jakefile,
+    params: &HashMap<String, String>,
+    nodes: &[&mut TaskNode],
+) -> Result<HashMap<String, String>> {
+    let mut values = HashMap::new();
+    for (name, var) in &jakefile.vars {
+        let value = match (params.get(name), var) {
+            (Some(overridden), _) => overridden.clone(),
+            (None, Var::Value(value)) => value.clone(),
+            (None, Var::Shell(command)) => {
+                if !nodes.iter().any(|node| references_var(node, name)) {
+                    continue;
+                }
+                evaluate_var(jakefile, name, command)?
+            }
+        };
+        values.insert(name.clone(), value);
+    }
+    Ok(values)
+}
+
+/// Whether a task uses the variable `name`, which it does not when it declares a
+/// parameter with the same name.
+fn references_var(task_node: &TaskNode, name: &str) -> bool {
+    if task_node.params.iter().any(|(param, _)| param == name) {
+        return false;
+    }
+    let dir = task_node
+        .options
+        .dir
+        .as_ref()
+        .map(|dir| dir.to_string_lossy().to_string());
+    std::iter::once(&task_node.command)
+        .chain(dir.as_ref())
+        .chain(task_node.options.env.iter().map(|(_, value)| value))
+        .chain(&task_node.sources)
+        .chain(&task_node.outputs)
+        .any(|text| placeholders(text).iter().any(|p| p == name))
+}
+
+/// Runs the command of a variable with the jakefile's shell, from the jakefile's
+/// directory, and returns its output without the trailing newlines.
+fn evaluate_var(jakefile: &Jakefile, name: &str, command: &str) -> Result<String> {
+    let options = TaskOptions {
+        shell: jakefile.settings.shell.clone(),
+        ..TaskOptions::default()
+    };
+    let shell = options.effective_shell();
+    let output = std::process::Command::new(shell[0])
+        .args(&shell[1..])
+        .arg(command)
+        .current_dir(&jakefile.dir)
+        .stderr(std::process::Stdio::inherit())
+        .output()
+        .with_context(|| format!("Could not evaluate the variable `{}`", name))?;
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Could not evaluate the variable `{}`: `{}` failed: {}",
+            name,
+            command,
+            ExitError::from(output.status)
+        ));
+    }
+    let value = String::from_utf8(output.stdout)
+        .with_context(|| format!("The output of the variable `{}` is not valid UTF-8", name))?;
+    Ok(value.trim_end_matches(['\n', '\r']).to_string())
+}
+
+/// Splits the value of `--options` into arguments, following the shell's quoting rules.
+pub fn split_options(flags: &str) -> Result<Vec<String>> {
+    shell_words::split(flags).map_err(|e| anyhow!("Could not parse the options `{}`: {}", flags, e))
+}
+
+/// Runs a single task, re-running its command (and only its command) when it fails
+/// and the task allows retries.
+fn run_task_node(
+    task_node: &TaskNode,
+    executor: &dyn Executor,
+    dotenv: &HashMap<String, String>,
+) -> Result<()> {
+    if task_node.command.trim().is_empty() {
+        return Err(anyhow!("Task {} has an empty command", task_node.name));
+    }
+    let context = || {
+        if task_node.options.interpreter.is_some() {
+            format!("Task {} failed while running its script", task_node.name)
+        } else {
+            format!(
+                "Task {} failed while running `{}`",
+                task_node.name, task_node.command
+            )
+        }
+    };
+    let attempts = task_node.retries + 1;
+    let mut delay = task_node.retry_delay;
+    let mut attempt = 1;
+    loop {
+        let result = executor.execute(
+            &task_node.command,
+            task_node.args.iter().map(String::as_str).collect(),
+            dotenv,
+            &task_node.options,
+        );
+        match result {
+            Err(e) if attempt < attempts && is_retryable(&e) && !interrupted() => {
+                eprintln!(
+                    "Task {} failed on attempt {}/{}: {}",
+                    task_node.name, attempt, attempts, e
+                );
+                if !delay.is_zero() {
+                    eprintln!("Retrying in {:?}", delay);
+                    sleep_unless_interrupted(delay);
+                    delay *= 2;
+                }
+                // Ctrl-C during the delay stops the retries too
+                if interrupted() {
+                    return Err(e).with_context(context);
+                }
+                attempt += 1;
+                eprintln!(
+                    "Running task {} (attempt {}/{})",
+                    task_node.name, attempt, attempts
+                );
+            }
+            _ => return result.with_context(context),
+        }
+    }
+}
+
+/// Only failures of the command itself are worth retrying: errors such as a missing
+/// .env file would fail again in the same way.
+fn is_retryable(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<ExitError>().is_some() || error.downcast_ref::<TimeoutError>().is_some()
+}
+
+/// The task run when none is given: `default`, or else the first task of the jakefile.
+fn default_task(jakefile_path: Option<&str>) -> Result<String> {
+    let available_tasks = parse_jakefile(jakefile_path)?;
+    if available_tasks.contains_key("default") {
+        return Ok("default".to_string());
+    }
+    match available_tasks.keys().find(|k| !is_reserved(k)) {
+        None => Err(anyhow!("could not find any task within jakefile")),
+        Some(task) => Ok(task.clone()),
+    }
+}
+
+/// The given tasks, or the default task when none is given.
+fn requested_tasks(jakefile_path: Option<&str>, tasks: &[String]) -> Result<Vec<String>> {
+    if tasks.is_empty() {
+        return Ok(vec![default_task(jakefile_path)?]);
+    }
+    Ok(tasks.to_vec())
+}
+
+pub fn execute_default_command(
+    jakefile_path: Option<&str>,
+    params: &HashMap<String, String>,
+    args: &[String],
+    executor: &dyn Executor,
+    run_options: &RunOptions,
+) -> Result<()> {
+    let task = default_task(jakefile_path)?;
+    execute_commands(jakefile_path, &[task], params, args, executor, run_options)
+}
+
+/// Prints, for each node that running the given tasks would go through, whether it would
+/// run and why, without running anything.
+pub fn explain_plan(
+    jakefile_path: Option<&str>,
+    tasks: &[String],
+    params: &HashMap<String, String>,
+    args: &[String],
+    force: bool,
+) -> Result<()> {
+    let tasks = requested_tasks(jakefile_path, tasks)?;
+    let jakefile = load_jakefile(jakefile_path)?;
+    let Plan { nodes, .. } = resolve_plan(&jakefile, &tasks, params, args)?;
+    let cache = FingerprintCache::new(&jakefile.dir);
+    let artifacts = ArtifactCache::from_env(&jakefile.dir);
+    let run_options = RunOptions {
+        force,
+        ..Default::default()
+    };
+    for task_node in &nodes {
+        let explanation = explain_node(task_node, &jakefile.dir, &cache, &artifacts, &run_options)?;
+        let decision = match explanation.decision {
+            Decision::Run => "will run",
+            Decision::UpToDate => "is up to date",
+            Decision::Restore => "will be restored from the cache",
+        };
+        println!(
+            "Task {} {}:\n  - {}",
+            task_node.id,
+            decision,
+            explanation.reasons.join("\n  - ")
+        );
+    }
+    Ok(())
+}
+
+/// Removes the `outputs` of the given tasks and of their dependencies, or of the default
+/// task when none is given, and forgets their last runs so that they run again.
+///
+/// The files and directories are listed first, and only removed once confirmed on the
+/// terminal, unless `yes` is set. With `dry_run`, they are only listed.
+pub fn clean_outputs(
+    jakefile_path: Option<&str>,
+    tasks: &[String],
+    params: &HashMap<String, String>,
+    yes: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let tasks = requested_tasks(jakefile_path, tasks)?;
+    let jakefile = load_jakefile(jakefile_path)?;
+    let Plan { nodes, .. } = resolve_plan(&jakefile, &tasks, params, &[])?;
+    let mut matches = vec![];
+    for task_node in &nodes {
+        matches.extend(matching_paths(&task_node.outputs, "outputs")?);
+    }
+    matches.sort();
+    // the content of a directory goes along with it, and sorts right after it
+    let mut paths: Vec<PathBuf> = vec![];
+    for path in matches {
+        if !paths.last().is_some_and(|kept| path.starts_with(kept)) {
+            paths.push(path);
+        }
+    }
+    if paths.is_empty() {
+        println!("There are no outputs to remove");
+    } else {
+        let listed: Vec<String> = paths.iter().map(|p| p.display().to_string()).collect();
+        println!(
+            "The following outputs will be removed:\n- {}",
+            listed.join("\n- ")
+        );
+    }
+    if dry_run {
+        return Ok(());
+    }
+    if !paths.is_empty() && !yes && !confirm("Remove them?")? {
+        println!("Nothing was removed");
+        return Ok(());
+    }
+    for path in &paths {
+        let removed = if path.is_dir() {
+            std::fs::remove_dir_all(path)
+        } else {
+            std::fs::remove_file(path)
+        };
+        removed.with_context(|| format!("Could not remove {}", path.display()))?;
+    }
+    let cache = FingerprintCache::new(&jakefile.dir);
+    for task_node in &nodes {
+        cache.remove(task_node)?;
+    }
+    Ok(())
+}
+
+/// Asks a yes/no question on the terminal, defaulting to no.
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin()
+        .read_line(&mut answer)
+        .context("Could not read the answer")?;
+    Ok(matches!(answer.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use serial_test::serial;
+
+    use crate::models::{CommandExecutor, DryRunExecutor, TaskOptions, append_args};
+
+    use super::*;
+
+    struct MockCommandExecutor;
+
+    impl MockCommandExecutor {
+        pub fn new() -> Self {
+            Self {}
         }
     }
 
@@ -236,7 +1620,8 @@ mod tests {
             &self,
             main_command: &str,
             args: Vec<&str>,
-            _load_env: bool,
+            _dotenv: &HashMap<String, String>,
+            _options: &TaskOptions,
         ) -> anyhow::Result<()> {
             let full_command = main_command.to_owned() + " " + &args.join(" ");
             std::fs::write("test.mock", full_command)?;
@@ -250,8 +1635,8 @@ mod tests {
         let result = parse_jakefile(Some("testfiles/jakefile.toml"));
         match result {
             Err(e) => {
-                println!("An error occurred: {}", e.to_string());
-                assert!(false); // fail here
+                println!("An error occurred: {}", e);
+                panic!(); // fail here
             }
             Ok(t) => {
                 assert!(t.contains_key("say-hello"));
@@ -262,7 +1647,7 @@ mod tests {
                 match t["say-hello"].as_table() {
                     None => {
                         println!("say-hello is not a table");
-                        assert!(false); // fail here
+                        panic!(); // fail here
                     }
                     Some(d) => {
                         assert!(d.contains_key("command"));
@@ -272,7 +1657,7 @@ mod tests {
                 match t["say-bye"].as_table() {
                     None => {
                         println!("say-bye is not a table");
-                        assert!(false); // fail here
+                        panic!(); // fail here
                     }
                     Some(d) => {
                         assert!(d.contains_key("command"));
@@ -282,7 +1667,7 @@ mod tests {
                 match t["strcmd"].as_str() {
                     None => {
                         println!("strcmd is not a string");
-                        assert!(false); // fail here
+                        panic!(); // fail here
                     }
                     Some(s) => {
                         assert_eq!(s, "echo ciao");
@@ -294,280 +1679,1428 @@ mod tests {
 
     #[test]
     #[serial]
-    fn test_mock_command_execution() {
-        let mock_executor = MockCommandExecutor::new();
-        let result = execute_command(
-            Some("testfiles/jakefile.toml"),
-            "list",
-            "-la /hello/something",
-            &mock_executor,
-            false,
+    fn test_mock_command_execution() {
+        let mock_executor = MockCommandExecutor::new();
+        let result = execute_commands(
+            Some("testfiles/jakefile.toml"),
+            &["list".to_string()],
+            &HashMap::new(),
+            &["-la".to_string(), "/hello/something".to_string()],
+            &mock_executor,
+            &RunOptions::default(),
+        );
+        assert!(result.is_ok());
+        let mock_content =
+            std::fs::read_to_string("test.mock").expect("Should be able to read test.mock");
+        assert_eq!(mock_content.trim(), "ls -la /hello/something");
+        let result_1 = execute_commands(
+            Some("testfiles/jakefile.toml"),
+            &["list".to_string()],
+            &HashMap::new(),
+            &[],
+            &mock_executor,
+            &RunOptions::default(),
+        );
+        assert!(result_1.is_ok());
+        let mock_content_1 =
+            std::fs::read_to_string("test.mock").expect("Should be able to read test.mock");
+        assert_eq!(mock_content_1.trim(), "ls");
+        let result_2 = execute_default_command(
+            Some("testfiles/jakefile.toml"),
+            &HashMap::new(),
+            &[],
+            &mock_executor,
+            &RunOptions::default(),
+        );
+        assert!(result_2.is_ok());
+        let mock_content_2 =
+            std::fs::read_to_string("test.mock").expect("Should be able to read test.mock");
+        assert_eq!(mock_content_2.trim(), "echo 'hello'");
+        let result_3 = execute_default_command(
+            Some("testfiles/withdefault.toml"),
+            &HashMap::new(),
+            &[],
+            &mock_executor,
+            &RunOptions::default(),
+        );
+        assert!(result_3.is_ok());
+        let mock_content_3 =
+            std::fs::read_to_string("test.mock").expect("Should be able to read test.mock");
+        assert_eq!(mock_content_3.trim(), "true");
+        let result_4 = execute_commands(
+            Some("testfiles/jakefile.toml"),
+            &["strcmd".to_string()],
+            &HashMap::new(),
+            &[],
+            &mock_executor,
+            &RunOptions::default(),
+        );
+        assert!(result_4.is_ok());
+        let mock_content_4 =
+            std::fs::read_to_string("test.mock").expect("Should be able to read test.mock");
+        assert_eq!(mock_content_4.trim(), "echo ciao");
+    }
+
+    #[test]
+    #[serial]
+    fn test_command_execution() {
+        let executor = CommandExecutor::new();
+        let result = execute_commands(
+            Some("testfiles/jakefile.toml"),
+            &["say-hello".to_string()],
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    #[serial]
+    fn test_command_execution_task_not_found() {
+        let executor = CommandExecutor::new();
+        let result = execute_commands(
+            Some("testfiles/jakefile.toml"),
+            &["say-ciao".to_string()],
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
+        );
+        assert!(result.is_err_and(|e| {
+            e.to_string()
+                == "Task say-ciao does not exist. Please define it within you jakefile.toml file"
+        }));
+    }
+
+    #[test]
+    #[serial]
+    fn test_command_execution_unexpected_format() {
+        let executor = CommandExecutor::new();
+        let result = execute_commands(
+            Some("testfiles/withdefault.toml"),
+            &["error".to_string()],
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
+        );
+        assert!(result.is_err_and(|e| e.to_string() == "Unsupported value for the task's command"));
+    }
+
+    #[test]
+    #[serial]
+    fn test_command_execution_no_command() {
+        let executor = CommandExecutor::new();
+        let result = execute_commands(
+            Some("testfiles/withdefault.toml"),
+            &["nocommand".to_string()],
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
+        );
+        assert!(result.is_err_and(
+            |e| e.to_string() == "`command` key not available for the requested task: ensure that there are no typos and the TOML syntax is correct before running again"
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn test_command_execution_wrong_command() {
+        let executor = CommandExecutor::new();
+        let result = execute_commands(
+            Some("testfiles/jakefile.toml"),
+            &["wrongcommand".to_string()],
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
+        );
+        assert!(result.is_err_and(|e| e.to_string() == "Unsupported value for the task's command"));
+    }
+
+    #[test]
+    #[serial]
+    fn test_command_execution_with_deps() {
+        let executor = CommandExecutor::new();
+        let result = execute_commands(
+            Some("testfiles/jakefile.toml"),
+            &["say-bye".to_string()],
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    #[serial]
+    fn test_command_execution_from_str() {
+        let executor = CommandExecutor::new();
+        let result = execute_commands(
+            Some("testfiles/jakefile.toml"),
+            &["strcmd".to_string()],
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    #[serial]
+    fn test_default_command_with_default() {
+        let executor = CommandExecutor::new();
+        let result = execute_default_command(
+            Some("testfiles/withdefault.toml"),
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    #[serial]
+    fn test_default_command_first_key() {
+        let executor = CommandExecutor::new();
+        let result = execute_default_command(
+            Some("testfiles/jakefile.toml"),
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
+        );
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    #[serial]
+    fn test_circular_deps_detection() {
+        let executor = CommandExecutor::new();
+        let result = execute_commands(
+            Some("testfiles/deps.toml"),
+            &["circular".to_string()],
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
+        );
+        assert!(result.is_err_and(
+            |e| e.to_string() == "Circular dependency issue detected with task circular"
+        ))
+    }
+
+    #[test]
+    #[serial]
+    fn test_dependency_not_found() {
+        let executor = CommandExecutor::new();
+        let result = execute_commands(
+            Some("testfiles/deps.toml"),
+            &["no-exist".to_string()],
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
+        );
+        assert!(result.is_err_and(|e| e.to_string()
+            == "Task no-deps does not exist. Please define it within you jakefile.toml file"));
+    }
+
+    #[test]
+    #[serial]
+    fn test_dependency_wrong_type() {
+        let executor = CommandExecutor::new();
+        let result = execute_commands(
+            Some("testfiles/deps.toml"),
+            &["calls-wrong".to_string()],
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
+        );
+        assert!(result.is_err_and(|e| e.to_string() == "Unsupported value for the task's command"));
+    }
+
+    #[test]
+    #[serial]
+    fn test_dependency_wrong_command_syntax() {
+        let executor = CommandExecutor::new();
+        let result = execute_commands(
+            Some("testfiles/deps.toml"),
+            &["calls-command".to_string()],
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
+        );
+        assert!(
+            result.is_err_and(
+                |e| e.to_string() == "`command` key not available for the requested task: ensure that there are no typos and the TOML syntax is correct before running again"
+            )
+        );
+    }
+
+    #[test]
+    #[serial]
+    fn test_command_execution_exit_code() {
+        let executor = CommandExecutor::new();
+        let result = execute_commands(
+            Some("testfiles/deps.toml"),
+            &["exit-code".to_string()],
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
+        );
+        let err = result.expect_err("A failing command should return an error");
+        assert_eq!(
+            err.to_string(),
+            "Task exit-code failed while running `exit 3`"
+        );
+        let exit_error = err
+            .downcast_ref::<ExitError>()
+            .expect("The error should carry the exit status");
+        assert_eq!(exit_error.code, 3);
+    }
+
+    #[test]
+    #[serial]
+    fn test_command_execution_killed_by_signal() {
+        let executor = CommandExecutor::new();
+        let result = execute_commands(
+            Some("testfiles/deps.toml"),
+            &["killed".to_string()],
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
         );
-        assert!(result.is_ok());
-        let mock_content =
-            std::fs::read_to_string("test.mock").expect("Should be able to read test.mock");
-        assert_eq!(mock_content.trim(), "ls -la /hello/something");
-        let result_1 = execute_command(
-            Some("testfiles/jakefile.toml"),
-            "list",
-            "",
-            &mock_executor,
-            false,
+        let err = result.expect_err("A killed command should return an error");
+        let exit_error = err
+            .downcast_ref::<ExitError>()
+            .expect("The error should carry the exit status");
+        assert_eq!(exit_error.signal, Some(9));
+        assert_eq!(exit_error.code, 137);
+    }
+
+    #[test]
+    #[serial]
+    fn test_failing_dependency_stops_execution() {
+        let executor = CommandExecutor::new();
+        let result = execute_commands(
+            Some("testfiles/deps.toml"),
+            &["after-failure".to_string()],
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
         );
-        assert!(result_1.is_ok());
-        let mock_content_1 =
-            std::fs::read_to_string("test.mock").expect("Should be able to read test.mock");
-        assert_eq!(mock_content_1.trim(), "ls");
-        let result_2 =
-            execute_default_command(Some("testfiles/jakefile.toml"), "", &mock_executor, false);
-        assert!(result_2.is_ok());
-        let mock_content_2 =
-            std::fs::read_to_string("test.mock").expect("Should be able to read test.mock");
-        assert_eq!(mock_content_2.trim(), "echo 'hello'");
-        let result_3 = execute_default_command(
-            Some("testfiles/withdefault.toml"),
-            "",
-            &mock_executor,
-            false,
+        assert!(
+            result.is_err_and(|e| e.to_string() == "Task exit-code failed while running `exit 3`")
         );
-        assert!(result_3.is_ok());
-        let mock_content_3 =
-            std::fs::read_to_string("test.mock").expect("Should be able to read test.mock");
-        assert_eq!(mock_content_3.trim(), "true");
-        let result_4 = execute_command(
-            Some("testfiles/jakefile.toml"),
-            "strcmd",
-            "",
-            &mock_executor,
-            false,
+        assert!(!Path::new("testfiles/after-failure.txt").exists());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("30").unwrap(), Duration::from_secs(30));
+        assert_eq!(parse_duration("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_duration("5m").unwrap(), Duration::from_secs(300));
+        assert_eq!(parse_duration("1h30m").unwrap(), Duration::from_secs(5400));
+        assert!(parse_duration("5 minutes").is_err());
+        assert!(parse_duration("m").is_err());
+        assert!(parse_duration("0s").is_err());
+    }
+
+    #[test]
+    #[serial]
+    fn test_command_execution_timeout() {
+        let executor = CommandExecutor::new();
+        let started = std::time::Instant::now();
+        let result = execute_commands(
+            Some("testfiles/deps.toml"),
+            &["timed-out".to_string()],
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
         );
-        assert!(result_4.is_ok());
-        let mock_content_4 =
-            std::fs::read_to_string("test.mock").expect("Should be able to read test.mock");
-        assert_eq!(mock_content_4.trim(), "echo ciao");
+        assert!(started.elapsed() < Duration::from_secs(5));
+        let err = result.expect_err("A timed out command should return an error");
+        assert_eq!(
+            format!("{:#}", err),
+            "Task timed-out failed while running `sleep 5`: command timed out after 200ms"
+        );
+        assert!(err.downcast_ref::<TimeoutError>().is_some());
+        let result_1 = execute_commands(
+            Some("testfiles/deps.toml"),
+            &["in-time".to_string()],
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
+        );
+        assert!(result_1.is_ok());
+        let result_2 = execute_commands(
+            Some("testfiles/deps.toml"),
+            &["bad-timeout".to_string()],
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
+        );
+        assert!(result_2.is_err_and(|e| e.to_string().starts_with("Invalid duration `5 minutes`")));
     }
 
     #[test]
     #[serial]
-    fn test_command_execution() {
+    fn test_command_execution_timeout_kills_process_group() {
         let executor = CommandExecutor::new();
-        let result = execute_command(
-            Some("testfiles/jakefile.toml"),
-            "say-hello",
-            "",
+        let result = execute_commands(
+            Some("testfiles/deps.toml"),
+            &["timed-out-group".to_string()],
+            &HashMap::new(),
+            &[],
             &executor,
-            false,
+            &RunOptions::default(),
+        );
+        assert!(result.is_err());
+        std::thread::sleep(Duration::from_millis(1000));
+        assert!(!Path::new("testfiles/timed-out-group.txt").exists());
+    }
+
+    #[test]
+    #[serial]
+    fn test_command_execution_timeout_has_empty_stdin() {
+        let result = execute_commands(
+            Some("testfiles/deps.toml"),
+            &["timed-read".to_string()],
+            &HashMap::new(),
+            &[],
+            &CommandExecutor::new(),
+            &RunOptions::default(),
         );
         assert!(result.is_ok());
+        let status = std::fs::read_to_string("testfiles/timed-read.txt")
+            .expect("Should be able to read file");
+        assert_eq!(status, "1\n");
+        std::fs::remove_file("testfiles/timed-read.txt").expect("Should remove file");
+    }
+
+    fn remove_if_exists(path: &str) {
+        if Path::new(path).exists() {
+            std::fs::remove_file(path).expect("Should be able to remove file");
+        }
     }
 
     #[test]
     #[serial]
-    fn test_command_execution_task_not_found() {
+    fn test_retries_rerun_only_the_failing_task() {
+        remove_if_exists("testfiles/flaky.txt");
+        remove_if_exists("testfiles/retry-dependency.txt");
         let executor = CommandExecutor::new();
-        let result = execute_command(
-            Some("testfiles/jakefile.toml"),
-            "say-ciao",
-            "",
+        let result = execute_commands(
+            Some("testfiles/retries.toml"),
+            &["flaky".to_string()],
+            &HashMap::new(),
+            &[],
             &executor,
-            false,
-        );
-        assert_eq!(
-            result.is_err_and(|e| {
-                e.to_string()
-                == "Task say-ciao does not exist. Please define it within you jakefile.toml file"
-                    .to_string()
-            }),
-            true
+            &RunOptions::default(),
         );
+        assert!(result.is_ok());
+        let dependency_runs = std::fs::read_to_string("testfiles/retry-dependency.txt")
+            .expect("Should be able to read file");
+        assert_eq!(dependency_runs.lines().count(), 1);
+        remove_if_exists("testfiles/flaky.txt");
+        remove_if_exists("testfiles/retry-dependency.txt");
     }
 
     #[test]
     #[serial]
-    fn test_command_execution_unexpected_format() {
+    fn test_retries_exhausted() {
+        remove_if_exists("testfiles/always-fails.txt");
         let executor = CommandExecutor::new();
-        let result = execute_command(
-            Some("testfiles/withdefault.toml"),
-            "error",
-            "",
+        let result = execute_commands(
+            Some("testfiles/retries.toml"),
+            &["always-fails".to_string()],
+            &HashMap::new(),
+            &[],
             &executor,
-            false,
+            &RunOptions::default(),
         );
-        assert_eq!(
-            result.is_err_and(
-                |e| e.to_string() == "Unsupported value for the task's command".to_string()
-            ),
-            true
+        assert!(result.is_err());
+        let attempts = std::fs::read_to_string("testfiles/always-fails.txt")
+            .expect("Should be able to read file");
+        assert_eq!(attempts.lines().count(), 3);
+        remove_if_exists("testfiles/always-fails.txt");
+        let result_1 = execute_commands(
+            Some("testfiles/retries.toml"),
+            &["bad-retries".to_string()],
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
+        );
+        assert!(
+            result_1.is_err_and(|e| e.to_string()
+                == "Unsupported value for `retries`: expected a non-negative integer")
         );
     }
 
     #[test]
     #[serial]
-    fn test_command_execution_no_command() {
+    fn test_command_execution_working_directory() {
         let executor = CommandExecutor::new();
-        let result = execute_command(
-            Some("testfiles/withdefault.toml"),
-            "nocommand",
-            "",
+        for task in ["in-jakefile-dir", "in-subdir"] {
+            let result = execute_commands(
+                Some("testfiles/workdir.toml"),
+                &[task.to_string()],
+                &HashMap::new(),
+                &[],
+                &executor,
+                &RunOptions::default(),
+            );
+            assert!(result.is_ok());
+        }
+        let result = execute_commands(
+            Some("testfiles/workdir.toml"),
+            &["in-missing-dir".to_string()],
+            &HashMap::new(),
+            &[],
             &executor,
-            false,
+            &RunOptions::default(),
         );
-        assert_eq!(result.is_err_and(
-            |e| e.to_string() == "`command` key not available for the requested task: ensure that there are no typos and the TOML syntax is correct before running again".to_string()
-        ), true);
+        assert!(result.is_err_and(|e| {
+            format!("{:#}", e)
+                .ends_with("Working directory testfiles/does-not-exist does not exist")
+        }));
     }
 
     #[test]
     #[serial]
-    fn test_command_execution_wrong_command() {
+    fn test_settings_are_not_tasks() {
+        let tasks = list_jakefile_tasks(Some("testfiles/workdir.toml"))
+            .expect("Should be able to list tasks");
+        assert!(!tasks.contains(&"settings".to_string()));
         let executor = CommandExecutor::new();
-        let result = execute_command(
-            Some("testfiles/jakefile.toml"),
-            "wrongcommand",
-            "",
+        let result = execute_commands(
+            Some("testfiles/workdir.toml"),
+            &["settings".to_string()],
+            &HashMap::new(),
+            &[],
             &executor,
-            false,
+            &RunOptions::default(),
         );
-        assert_eq!(
-            result.is_err_and(
-                |e| e.to_string() == "Unsupported value for the task's command".to_string()
-            ),
-            true
+        assert!(result.is_err_and(|e| e.to_string()
+            == "`settings` is a reserved key of jakefile.toml and cannot be executed as a task"));
+        let result_1 = execute_commands(
+            Some("testfiles/bad-settings.toml"),
+            &["task".to_string()],
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
+        );
+        assert!(
+            result_1.is_err_and(|e| e.to_string() == "Unknown setting `unknown` in `settings`")
         );
     }
 
     #[test]
     #[serial]
-    fn test_command_execution_with_deps() {
+    fn test_command_load_dotenv_variable() {
         let executor = CommandExecutor::new();
-        let result = execute_command(
+        let result = execute_commands(
             Some("testfiles/jakefile.toml"),
-            "say-bye",
-            "",
+            &["env_var".to_string()],
+            &HashMap::new(),
+            &[],
             &executor,
-            false,
+            &RunOptions {
+                load_env: true,
+                ..Default::default()
+            },
         );
         assert!(result.is_ok());
     }
 
     #[test]
     #[serial]
-    fn test_command_execution_from_str() {
+    fn test_command_task_env_overrides_dotenv() {
         let executor = CommandExecutor::new();
-        let result = execute_command(
-            Some("testfiles/jakefile.toml"),
-            "strcmd",
-            "",
+        let result = execute_commands(
+            Some("testfiles/deps.toml"),
+            &["with-env".to_string()],
+            &HashMap::new(),
+            &[],
             &executor,
-            false,
+            &RunOptions {
+                load_env: true,
+                ..Default::default()
+            },
         );
         assert!(result.is_ok());
+        let result_1 = execute_commands(
+            Some("testfiles/deps.toml"),
+            &["bad-env".to_string()],
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
+        );
+        assert!(result_1.is_err_and(|e| e.to_string()
+            == "Unsupported value for environment variable `LIST`: expected a string, a number or a boolean"));
     }
 
     #[test]
     #[serial]
-    fn test_default_command_with_default() {
+    fn test_command_execution_custom_shell() {
         let executor = CommandExecutor::new();
-        let result =
-            execute_default_command(Some("testfiles/withdefault.toml"), "", &executor, false);
-        assert!(result.is_ok());
+        let run = |task: &str| {
+            execute_commands(
+                Some("testfiles/shell.toml"),
+                &[task.to_string()],
+                &HashMap::new(),
+                &[],
+                &executor,
+                &RunOptions::default(),
+            )
+        };
+        assert!(run("pipefail").is_err());
+        assert!(run("plain-sh").is_ok());
+        assert!(run("bash-only").is_ok());
+        assert!(
+            run("empty-shell")
+                .is_err_and(|e| e.to_string().starts_with("Unsupported value for `shell`"))
+        );
     }
 
     #[test]
     #[serial]
-    fn test_default_command_first_key() {
+    fn test_script_recipes() {
         let executor = CommandExecutor::new();
-        let result = execute_default_command(Some("testfiles/jakefile.toml"), "", &executor, false);
-        assert!(result.is_ok());
+        let run = |task: &str, flags: &str| {
+            execute_commands(
+                Some("testfiles/scripts.toml"),
+                &[task.to_string()],
+                &HashMap::new(),
+                &split_options(flags).expect("Should be able to split options"),
+                &executor,
+                &RunOptions::default(),
+            )
+        };
+        assert!(run("python", "first second").is_ok());
+        assert!(run("shebang", "first").is_ok());
+        // the script file is only readable by the current user
+        assert!(run("private", "").is_ok());
+        let err = run("failing", "").expect_err("The script should fail");
+        assert_eq!(
+            err.to_string(),
+            "Task failing failed while running its script"
+        );
+        assert_eq!(
+            err.downcast_ref::<ExitError>()
+                .expect("The error should carry the exit status")
+                .code,
+            4
+        );
+        assert!(run("no-interpreter", "").is_err_and(|e| e.to_string()
+            == "Task no-interpreter defines a `script` without an `interpreter` or a shebang line"));
+        assert!(run("both", "").is_err_and(|e| e.to_string()
+            == "Task both defines both `command` and `script`: only one of them can be used"));
     }
 
     #[test]
     #[serial]
-    fn test_circular_deps_detection() {
+    fn test_command_text_is_preserved() {
         let executor = CommandExecutor::new();
-        let result = execute_command(
-            Some("testfiles/deps.toml"),
-            "circular",
-            "",
-            &executor,
-            false,
+        let run = |task: &str| {
+            execute_commands(
+                Some("testfiles/verbatim.toml"),
+                &[task.to_string()],
+                &HashMap::new(),
+                &[],
+                &executor,
+                &RunOptions::default(),
+            )
+        };
+        assert!(run("spacing").is_ok());
+        assert!(run("continuation").is_ok());
+        remove_if_exists("testfiles/heredoc.txt");
+        assert!(run("heredoc").is_ok());
+        let content =
+            std::fs::read_to_string("testfiles/heredoc.txt").expect("Should be able to read file");
+        assert_eq!(content, "line one\n  line two\n");
+        remove_if_exists("testfiles/heredoc.txt");
+    }
+
+    #[test]
+    #[serial]
+    fn test_options_rejected_for_multi_line_command() {
+        remove_if_exists("testfiles/heredoc.txt");
+        let result = execute_commands(
+            Some("testfiles/verbatim.toml"),
+            &["heredoc".to_string()],
+            &HashMap::new(),
+            &["extra".to_string()],
+            &CommandExecutor::new(),
+            &RunOptions::default(),
         );
+        assert!(result.is_err_and(|e| format!("{:#}", e).ends_with(
+            "Extra options cannot be forwarded to a multi-line command: use a `script`, which receives them as arguments"
+        )));
+        assert!(!Path::new("testfiles/heredoc.txt").exists());
+        assert!(append_args("echo first\necho second\n", &["--flag"]).is_err());
         assert_eq!(
-            result.is_err_and(|e| e.to_string()
-                == "Circular dependency issue detected with task circular".to_string()),
-            true
-        )
+            append_args("echo first\necho second\n", &[])
+                .expect("Should be able to run without options"),
+            "echo first\necho second\n"
+        );
+        assert_eq!(
+            append_args("git commit", &["-m", "fix bug"])
+                .expect("Should be able to append options"),
+            "git commit -m 'fix bug'"
+        );
     }
 
     #[test]
     #[serial]
-    fn test_dependency_not_found() {
+    fn test_options_are_tokenized_and_quoted() {
         let executor = CommandExecutor::new();
-        let result = execute_command(
-            Some("testfiles/deps.toml"),
-            "no-exist",
-            "",
-            &executor,
-            false,
-        );
+        let run = |flags: &str| {
+            execute_commands(
+                Some("testfiles/verbatim.toml"),
+                &["print-args".to_string()],
+                &HashMap::new(),
+                &split_options(flags).expect("Should be able to split options"),
+                &executor,
+                &RunOptions::default(),
+            )
+        };
+        assert!(run("-m 'fix bug' \"a  b\"").is_ok());
+        let content =
+            std::fs::read_to_string("testfiles/args.txt").expect("Should be able to read file");
+        assert_eq!(content, "-m|fix bug|a  b|");
+        assert!(run("'$(touch testfiles/injected.txt)' ; rm testfiles/args.txt").is_ok());
+        assert!(!Path::new("testfiles/injected.txt").exists());
+        let content =
+            std::fs::read_to_string("testfiles/args.txt").expect("Should be able to read file");
         assert_eq!(
-            result.is_err_and(|e| e.to_string()
-                == "Task no-deps does not exist. Please define it within you jakefile.toml file"
-                    .to_string()),
-            true
+            content,
+            "$(touch testfiles/injected.txt)|;|rm|testfiles/args.txt|"
         );
+        remove_if_exists("testfiles/args.txt");
+        assert!(split_options("'unterminated").is_err_and(|e| {
+            e.to_string()
+                .starts_with("Could not parse the options `'unterminated`")
+        }));
     }
 
     #[test]
     #[serial]
-    fn test_dependency_wrong_type() {
+    fn test_execute_multiple_tasks() {
+        remove_if_exists("testfiles/multi.txt");
         let executor = CommandExecutor::new();
-        let result = execute_command(
-            Some("testfiles/deps.toml"),
-            "calls-wrong",
-            "",
+        let tasks = ["fmt", "lint", "test"].map(String::from);
+        let result = execute_commands(
+            Some("testfiles/multi.toml"),
+            &tasks,
+            &HashMap::new(),
+            &[],
             &executor,
-            false,
+            &RunOptions::default(),
         );
-        assert_eq!(
-            result.is_err_and(
-                |e| e.to_string() == "Unsupported value for the task's command".to_string()
-            ),
-            true
+        assert!(result.is_ok());
+        let content =
+            std::fs::read_to_string("testfiles/multi.txt").expect("Should be able to read file");
+        assert_eq!(content, "setup\nfmt\nlint\ntest\n");
+        remove_if_exists("testfiles/multi.txt");
+        let result_1 = execute_commands(
+            Some("testfiles/multi.toml"),
+            &tasks,
+            &HashMap::new(),
+            &["--flag".to_string()],
+            &executor,
+            &RunOptions::default(),
         );
+        assert!(result_1.is_err_and(|e| e.to_string()
+            == "Extra options can only be forwarded when a single task is executed"));
     }
 
     #[test]
     #[serial]
-    fn test_dependency_wrong_command_syntax() {
+    fn test_task_parameters() {
         let executor = CommandExecutor::new();
-        let result = execute_command(
-            Some("testfiles/deps.toml"),
-            "calls-command",
-            "",
-            &executor,
-            false,
+        let run = |task: &str, params: &[(&str, &str)]| {
+            let params: HashMap<String, String> = params
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            execute_commands(
+                Some("testfiles/params.toml"),
+                &[task.to_string()],
+                &params,
+                &[],
+                &executor,
+                &RunOptions::default(),
+            )
+        };
+        assert!(run("build", &[("target", "x86_64"), ("profile", "release")]).is_ok());
+        let content =
+            std::fs::read_to_string("testfiles/params.txt").expect("Should be able to read file");
+        assert_eq!(content, "x86_64 release\n");
+        assert!(run("build", &[("target", "aarch64")]).is_ok());
+        let content =
+            std::fs::read_to_string("testfiles/params.txt").expect("Should be able to read file");
+        assert_eq!(content, "aarch64 debug\n");
+        // values are quoted, so they cannot run other commands
+        assert!(run("build", &[("target", "x; echo injected")]).is_ok());
+        let content =
+            std::fs::read_to_string("testfiles/params.txt").expect("Should be able to read file");
+        assert_eq!(content, "x; echo injected debug\n");
+        remove_if_exists("testfiles/params.txt");
+        assert!(run("in-env", &[("target", "x86_64")]).is_ok());
+        assert!(run("build", &[]).is_err_and(|e| e.to_string()
+            == "Task build requires the parameter `target`: pass it as `target=<value>`"));
+        assert!(
+            run("build", &[("target", "x86_64"), ("profile", "fast")]).is_err_and(|e| e
+                .to_string()
+                == "Invalid value `fast` for the parameter `profile` of task build: expected one of debug, release")
         );
-        assert_eq!(
-            result.is_err_and(
-                |e| e.to_string() == "`command` key not available for the requested task: ensure that there are no typos and the TOML syntax is correct before running again".to_string()
-            ),
-            true
+        assert!(
+            run("build", &[("target", "x86_64"), ("jobs", "4")]).is_err_and(|e| e.to_string()
+                == "Unknown parameter `jobs`: it is neither declared by the requested tasks nor defined in `vars`")
+        );
+        assert!(
+            run("bad-params", &[])
+                .is_err_and(|e| e.to_string().starts_with("Unsupported value for `params`"))
         );
+        assert!(!Path::new("testfiles/params.txt").exists());
     }
 
     #[test]
     #[serial]
-    fn test_command_load_dotenv_variable() {
+    fn test_jakefile_vars() {
         let executor = CommandExecutor::new();
-        let result = execute_command(
-            Some("testfiles/jakefile.toml"),
-            "env_var",
-            "",
-            &executor,
-            true,
+        let run = |task: &str, params: &[(&str, &str)]| {
+            let params: HashMap<String, String> = params
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            execute_commands(
+                Some("testfiles/vars.toml"),
+                &[task.to_string()],
+                &params,
+                &[],
+                &executor,
+                &RunOptions::default(),
+            )
+        };
+        let read =
+            || std::fs::read_to_string("testfiles/vars.txt").expect("Should be able to read file");
+        assert!(run("deploy", &[]).is_ok());
+        assert_eq!(read(), "ghcr.io/acme/app:latest\n");
+        assert!(run("deploy", &[("image", "docker.io/acme/app")]).is_ok());
+        assert_eq!(read(), "docker.io/acme/app:latest\n");
+        // the task's own parameter shadows the variable with the same name
+        assert!(run("release", &[]).is_ok());
+        assert_eq!(read(), "ghcr.io/acme/app:stable\n");
+        assert!(run("release", &[("tag", "v1")]).is_ok());
+        assert_eq!(read(), "ghcr.io/acme/app:v1\n");
+        remove_if_exists("testfiles/vars.txt");
+        assert!(run("in-dir", &[]).is_ok());
+        // an absolute directory is used as it is
+        let workdir =
+            std::fs::canonicalize("testfiles/workdir").expect("Should resolve the directory");
+        assert!(run("in-dir", &[("workdir", &workdir.to_string_lossy())]).is_ok());
+        assert!(run("in-env", &[]).is_ok());
+        assert!(run("in-env", &[("image", "other")]).is_err());
+        assert!(run("vars", &[]).is_err_and(|e| e.to_string()
+            == "`vars` is a reserved key of jakefile.toml and cannot be executed as a task"));
+        let tasks =
+            list_jakefile_tasks(Some("testfiles/vars.toml")).expect("Should be able to list tasks");
+        assert_eq!(tasks, vec!["deploy", "in-dir", "in-env", "release"]);
+    }
+
+    #[test]
+    #[serial]
+    fn test_jakefile_shell_vars() {
+        let executor = CommandExecutor::new();
+        let run = |task: &str, params: &[(&str, &str)]| {
+            let params: HashMap<String, String> = params
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            execute_commands(
+                Some("testfiles/sh-vars.toml"),
+                &[task.to_string()],
+                &params,
+                &[],
+                &executor,
+                &RunOptions::default(),
+            )
+        };
+        // evaluated once, although two tasks of the run reference it
+        assert!(run("twice", &[]).is_ok());
+        let content =
+            std::fs::read_to_string("testfiles/sh-vars.txt").expect("Should be able to read file");
+        assert_eq!(content, "v1.2.3\nv1.2.3\n");
+        let count = std::fs::read_to_string("testfiles/sh-vars.count")
+            .expect("Should be able to read file");
+        assert_eq!(count, "evaluated\n");
+        remove_if_exists("testfiles/sh-vars.txt");
+        remove_if_exists("testfiles/sh-vars.count");
+        // not evaluated when no task references them, or when overridden
+        assert!(run("unused", &[]).is_ok());
+        assert!(run("shadowed", &[]).is_ok());
+        assert!(run("uses-broken", &[("broken", "fixed")]).is_ok());
+        assert!(!Path::new("testfiles/sh-vars.count").exists());
+        assert!(run("uses-broken", &[]).is_err_and(|e| e.to_string()
+            == "Could not evaluate the variable `broken`: `exit 3` failed: command exited with status 3"));
+    }
+
+    #[test]
+    #[serial]
+    fn test_dependencies_with_arguments() {
+        let executor = CommandExecutor::new();
+        let run = |task: &str| {
+            execute_commands(
+                Some("testfiles/dep-args.toml"),
+                &[task.to_string()],
+                &HashMap::new(),
+                &[],
+                &executor,
+                &RunOptions::default(),
+            )
+        };
+        remove_if_exists("testfiles/dep-args.txt");
+        assert!(run("all").is_ok());
+        let content =
+            std::fs::read_to_string("testfiles/dep-args.txt").expect("Should be able to read file");
+        // `build` and `{ task = "build" }` are the same node, and run only once
+        assert_eq!(content, "build debug\nbuild release\nprint a  b\nprint\n");
+        remove_if_exists("testfiles/dep-args.txt");
+        assert!(run("bad-entry").is_err_and(|e| {
+            e.to_string()
+                .starts_with("Unsupported value for the `depends_on` of task bad-entry")
+        }));
+        assert!(run("bad-key").is_err_and(
+            |e| e.to_string() == "Unknown key `option` in the `depends_on` of task bad-key"
+        ));
+        assert!(run("undeclared").is_err_and(|e| e.to_string()
+            == "Task undeclared passes the parameter `profile` to task print, which does not declare it"));
+        assert!(!Path::new("testfiles/dep-args.txt").exists());
+    }
+
+    #[test]
+    #[serial]
+    fn test_dependency_order_and_run_after() {
+        let executor = CommandExecutor::new();
+        let run = |tasks: &[&str], jobs: usize| {
+            let tasks: Vec<String> = tasks.iter().map(|t| t.to_string()).collect();
+            let run_options = RunOptions {
+                jobs,
+                ..Default::default()
+            };
+            let result = execute_commands(
+                Some("testfiles/run-after.toml"),
+                &tasks,
+                &HashMap::new(),
+                &[],
+                &executor,
+                &run_options,
+            );
+            let content = std::fs::read_to_string("testfiles/run-after.txt").unwrap_or_default();
+            remove_if_exists("testfiles/run-after.txt");
+            result.map(|_| content)
+        };
+        for _ in 0..5 {
+            assert_eq!(run(&["declared"], 1).unwrap(), "c\nb\na\n");
+        }
+        // `run_after` does not trigger the other task, but orders it first when it runs
+        assert_eq!(run(&["seed"], 1).unwrap(), "seed\n");
+        assert_eq!(run(&["seed", "migrate"], 1).unwrap(), "migrate\nseed\n");
+        assert_eq!(run(&["setup"], 1).unwrap(), "migrate\nseed\n");
+        assert_eq!(run(&["setup"], 4).unwrap(), "migrate\nseed\n");
+        assert!(run(&["loop-b"], 1).is_err_and(|e| {
+            e.to_string()
+                .starts_with("Circular ordering detected between tasks loop-a, loop-b")
+        }));
+        assert!(run(&["missing"], 1).is_err_and(
+            |e| e.to_string() == "Task missing must run after task nope, which does not exist"
+        ));
+    }
+
+    #[test]
+    #[serial]
+    fn test_finally_and_on_failure_tasks() {
+        let executor = CommandExecutor::new();
+        let run = |task: &str| {
+            let result = execute_commands(
+                Some("testfiles/hooks.toml"),
+                &[task.to_string()],
+                &HashMap::new(),
+                &[],
+                &executor,
+                &RunOptions::default(),
+            );
+            let content = std::fs::read_to_string("testfiles/hooks.txt").unwrap_or_default();
+            remove_if_exists("testfiles/hooks.txt");
+            (result, content)
+        };
+        let (result, content) = run("succeed");
+        assert!(result.is_ok());
+        assert_eq!(content, "succeed\nflush\nstop-db\n");
+        let (result, content) = run("fail");
+        assert!(result.is_err_and(|e| e.downcast_ref::<ExitError>().unwrap().code == 3));
+        assert_eq!(content, "fail\ncollect-logs\nflush\nstop-db now\n");
+        // a failing hook fails the run, without preventing the other hooks
+        let (result, content) = run("failing-hook");
+        assert!(result.is_err_and(|e| e.downcast_ref::<ExitError>().unwrap().code == 4));
+        assert_eq!(content, "failing-hook\nflush\nstop-db\n");
+    }
+
+    #[test]
+    #[serial]
+    fn test_hooks_run_when_the_cache_cannot_be_written() {
+        let _ = std::fs::remove_dir_all("testfiles/incremental");
+        std::fs::create_dir_all("testfiles/incremental").expect("Should create the directory");
+        std::fs::write(
+            "testfiles/incremental/jakefile.toml",
+            r#"build = { command = "true", sources = ["jakefile.toml"], finally = ["cleanup"] }
+cleanup = "echo cleanup > testfiles/incremental/cleanup.txt"
+"#,
+        )
+        .expect("Should write file");
+        // the cache directory cannot be created where a file stands
+        std::fs::write("testfiles/incremental/.jake", "").expect("Should write file");
+        let result = execute_commands(
+            Some("testfiles/incremental/jakefile.toml"),
+            &["build".to_string()],
+            &HashMap::new(),
+            &[],
+            &CommandExecutor::new(),
+            &RunOptions::default(),
+        );
+        assert!(result.is_err_and(|e| e.to_string().starts_with("Could not create")));
+        assert!(Path::new("testfiles/incremental/cleanup.txt").exists());
+        std::fs::remove_dir_all("testfiles/incremental").expect("Should remove the directory");
+    }
+
+    #[test]
+    #[serial]
+    fn test_background_tasks() {
+        let executor = CommandExecutor::new();
+        let run = |task: &str| {
+            let result = execute_commands(
+                Some("testfiles/background.toml"),
+                &[task.to_string()],
+                &HashMap::new(),
+                &[],
+                &executor,
+                &RunOptions::default(),
+            );
+            let content = std::fs::read_to_string("testfiles/background.txt").unwrap_or_default();
+            remove_if_exists("testfiles/background.txt");
+            (result, content)
+        };
+        let start = std::time::Instant::now();
+        let (result, content) = run("e2e");
+        assert!(result.is_ok());
+        assert_eq!(content, "e2e\nstopped\n");
+        assert!(std::net::TcpStream::connect(("127.0.0.1", 48213)).is_err());
+        let (result, content) = run("after-file");
+        remove_if_exists("testfiles/background.ready");
+        assert!(result.is_ok());
+        assert_eq!(content, "after-file\n");
+        let (result, content) = run("after-command");
+        assert!(result.is_ok());
+        assert_eq!(content, "after-command\n");
+        // the long-running commands have been terminated instead of waited for
+        assert!(start.elapsed() < Duration::from_secs(10));
+        let (result, _) = run("after-never-ready");
+        assert!(result.is_err_and(|e| format!("{:#}", e)
+            == "Background task never-ready failed to start: command did not become ready within 300ms"));
+        // a check command that does not complete is killed at the deadline
+        let start = std::time::Instant::now();
+        let (result, _) = run("after-slow-check");
+        assert!(result.is_err_and(|e| format!("{:#}", e)
+            == "Background task slow-check failed to start: command did not become ready within 300ms"));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        let (result, _) = run("after-exit");
+        assert!(result.is_err_and(|e| e.downcast_ref::<ExitError>().unwrap().code == 5));
+        let (result, content) = run("solo");
+        assert!(result.is_ok());
+        assert_eq!(content, "solo\n");
+        let (result, _) = run("not-background");
+        assert!(result.is_err_and(|e| e.to_string()
+            == "Task not-background sets `ready_when` but does not run in the `background`"));
+    }
+
+    #[test]
+    #[serial]
+    fn test_sources_and_outputs() {
+        let executor = CommandExecutor::new();
+        let run = |task: &str, force: bool| {
+            let run_options = RunOptions {
+                force,
+                ..Default::default()
+            };
+            execute_commands(
+                Some("testfiles/incremental.toml"),
+                &[task.to_string()],
+                &HashMap::new(),
+                &[],
+                &executor,
+                &run_options,
+            )
+        };
+        let runs = || {
+            std::fs::read_to_string("testfiles/incremental/ran.log")
+                .unwrap_or_default()
+                .lines()
+                .count()
+        };
+        let _ = std::fs::remove_dir_all("testfiles/incremental");
+        let _ = std::fs::remove_dir_all("testfiles/.jake");
+        std::fs::create_dir_all("testfiles/incremental/src/nested")
+            .expect("Should be able to create the directory");
+        std::fs::write("testfiles/incremental/src/a.txt", "a\n").expect("Should write file");
+        std::fs::write("testfiles/incremental/src/nested/b.txt", "b\n").expect("Should write file");
+        // missing output
+        assert!(run("build", false).is_ok());
+        assert_eq!(runs(), 1);
+        // every output is newer than every source
+        assert!(run("build", false).is_ok());
+        assert_eq!(runs(), 1);
+        assert!(run("build", true).is_ok());
+        assert_eq!(runs(), 2);
+        std::thread::sleep(Duration::from_millis(20));
+        std::fs::write("testfiles/incremental/src/nested/b.txt", "c\n").expect("Should write file");
+        assert!(run("build", false).is_ok());
+        assert_eq!(runs(), 3);
+        let content = std::fs::read_to_string("testfiles/incremental/out/app.txt")
+            .expect("Should be able to read file");
+        assert_eq!(content, "a\n");
+        std::fs::remove_dir_all("testfiles/incremental/out").expect("Should remove outputs");
+        std::fs::remove_dir_all("testfiles/.jake/artifacts").expect("Should remove the cache");
+        assert!(run("build", false).is_ok());
+        assert_eq!(runs(), 4);
+        // without outputs, there is nothing to compare the sources with
+        assert!(run("no-outputs", false).is_ok());
+        assert_eq!(runs(), 5);
+        assert!(run("bad-pattern", false).is_err_and(|e| {
+            e.to_string()
+                .starts_with("Invalid pattern `testfiles/[a` in `sources`")
+        }));
+        std::fs::remove_dir_all("testfiles/incremental").expect("Should remove the directory");
+        std::fs::remove_dir_all("testfiles/.jake").expect("Should remove the cache");
+    }
+
+    #[test]
+    #[serial]
+    fn test_artifact_cache() {
+        let executor = CommandExecutor::new();
+        let run = |task: &str| {
+            execute_commands(
+                Some("testfiles/incremental.toml"),
+                &[task.to_string()],
+                &HashMap::new(),
+                &[],
+                &executor,
+                &RunOptions::default(),
+            )
+        };
+        let runs = || {
+            std::fs::read_to_string("testfiles/incremental/ran.log")
+                .unwrap_or_default()
+                .lines()
+                .count()
+        };
+        let bundle = || {
+            std::fs::read_to_string("testfiles/incremental/dist/bundle.sh")
+                .expect("Should be able to read file")
+        };
+        let _ = std::fs::remove_dir_all("testfiles/incremental");
+        let _ = std::fs::remove_dir_all("testfiles/.jake");
+        std::fs::create_dir_all("testfiles/incremental/src")
+            .expect("Should be able to create the directory");
+        std::fs::write("testfiles/incremental/src/a.txt", "echo a\n").expect("Should write file");
+        assert!(run("bundle").is_ok());
+        std::fs::write("testfiles/incremental/src/a.txt", "echo b\n").expect("Should write file");
+        assert!(run("bundle").is_ok());
+        assert_eq!(runs(), 2);
+        assert_eq!(bundle(), "echo b\n");
+        // back to the inputs of the first run: its outputs are restored instead
+        std::fs::write("testfiles/incremental/src/a.txt", "echo a\n").expect("Should write file");
+        std::fs::write("testfiles/incremental/dist/stale.txt", "").expect("Should write file");
+        assert!(run("bundle").is_ok());
+        assert_eq!(runs(), 2);
+        assert_eq!(bundle(), "echo a\n");
+        assert!(!Path::new("testfiles/incremental/dist/stale.txt").exists());
+        let mode = std::fs::metadata("testfiles/incremental/dist/bundle.sh")
+            .expect("Should read the metadata")
+            .permissions()
+            .mode();
+        assert_eq!(mode & 0o111, 0o111);
+        // the restored outputs are up to date for the next run
+        assert!(run("bundle").is_ok());
+        assert_eq!(runs(), 2);
+        std::fs::remove_dir_all("testfiles/incremental").expect("Should remove the directory");
+        std::fs::remove_dir_all("testfiles/.jake").expect("Should remove the cache");
+    }
+
+    #[test]
+    #[serial]
+    fn test_clean_outputs() {
+        let clean = |dry_run: bool| {
+            clean_outputs(
+                Some("testfiles/incremental.toml"),
+                &["package".to_string()],
+                &HashMap::new(),
+                true,
+                dry_run,
+            )
+        };
+        let _ = std::fs::remove_dir_all("testfiles/incremental");
+        let _ = std::fs::remove_dir_all("testfiles/.jake");
+        std::fs::create_dir_all("testfiles/incremental/src")
+            .expect("Should be able to create the directory");
+        std::fs::write("testfiles/incremental/src/a.txt", "a\n").expect("Should write file");
+        let result = execute_commands(
+            Some("testfiles/incremental.toml"),
+            &["package".to_string()],
+            &HashMap::new(),
+            &[],
+            &CommandExecutor::new(),
+            &RunOptions::default(),
         );
         assert!(result.is_ok());
+        let jakefile = load_jakefile(Some("testfiles/incremental.toml"))
+            .expect("Should be able to load the jakefile");
+        let Plan { nodes, .. } =
+            resolve_plan(&jakefile, &["build".to_string()], &HashMap::new(), &[])
+                .expect("Should be able to resolve the plan");
+        let cache = FingerprintCache::new(&jakefile.dir);
+        assert!(cache.load(&nodes[0]).is_some());
+        assert!(clean(true).is_ok());
+        assert!(Path::new("testfiles/incremental/app.txt").exists());
+        assert!(cache.load(&nodes[0]).is_some());
+        // the outputs of the dependency are removed too, along with its fingerprint
+        assert!(clean(false).is_ok());
+        assert!(!Path::new("testfiles/incremental/app.txt").exists());
+        assert!(!Path::new("testfiles/incremental/out").exists());
+        assert!(Path::new("testfiles/incremental/src/a.txt").exists());
+        assert!(cache.load(&nodes[0]).is_none());
+        // nothing left to remove
+        assert!(clean(false).is_ok());
+        std::fs::remove_dir_all("testfiles/incremental").expect("Should remove the directory");
+        std::fs::remove_dir_all("testfiles/.jake").expect("Should remove the cache");
+    }
+
+    #[test]
+    #[serial]
+    fn test_explain() {
+        let jakefile = load_jakefile(Some("testfiles/incremental.toml"))
+            .expect("Should be able to load the jakefile");
+        let cache = FingerprintCache::new(&jakefile.dir);
+        let artifacts = ArtifactCache::from_env(&jakefile.dir);
+        let explain_with = |task: &str, params: &HashMap<String, String>, force: bool| {
+            let Plan { nodes, .. } = resolve_plan(&jakefile, &[task.to_string()], params, &[])
+                .expect("Should be able to resolve the plan");
+            let run_options = RunOptions {
+                force,
+                ..Default::default()
+            };
+            explain_node(
+                nodes.last().expect("Should resolve the task"),
+                &jakefile.dir,
+                &cache,
+                &artifacts,
+                &run_options,
+            )
+            .expect("Should be able to explain the task")
+        };
+        let explain = |force: bool| explain_with("build", &HashMap::new(), force);
+        let run_with = |task: &str, params: &HashMap<String, String>| {
+            execute_commands(
+                Some("testfiles/incremental.toml"),
+                &[task.to_string()],
+                params,
+                &[],
+                &CommandExecutor::new(),
+                &RunOptions::default(),
+            )
+        };
+        let run = || run_with("build", &HashMap::new());
+        let _ = std::fs::remove_dir_all("testfiles/incremental");
+        let _ = std::fs::remove_dir_all("testfiles/.jake");
+        std::fs::create_dir_all("testfiles/incremental/src")
+            .expect("Should be able to create the directory");
+        std::fs::write("testfiles/incremental/src/a.txt", "a\n").expect("Should write file");
+        let explanation = explain(false);
+        assert_eq!(explanation.decision, Decision::Run);
+        assert_eq!(
+            explanation.reasons,
+            vec![
+                "output `incremental/out` is missing",
+                "no successful run has been recorded"
+            ]
+        );
+        assert!(run().is_ok());
+        assert_eq!(explain(false).decision, Decision::UpToDate);
+        let explanation = explain(true);
+        assert_eq!(explanation.decision, Decision::Run);
+        assert_eq!(explanation.reasons, vec!["it is forced with `--force`"]);
+        std::fs::write("testfiles/incremental/src/a.txt", "b\n").expect("Should write file");
+        std::fs::write("testfiles/incremental/src/b.txt", "b\n").expect("Should write file");
+        let explanation = explain(false);
+        assert_eq!(explanation.decision, Decision::Run);
+        assert_eq!(
+            explanation.reasons,
+            vec![
+                "source `incremental/src/a.txt` changed since the last run",
+                "source `incremental/src/b.txt` was added"
+            ]
+        );
+        // back to the inputs of the first run
+        assert!(run().is_ok());
+        std::fs::write("testfiles/incremental/src/a.txt", "a\n").expect("Should write file");
+        std::fs::remove_file("testfiles/incremental/src/b.txt").expect("Should remove file");
+        assert_eq!(explain(false).decision, Decision::Restore);
+        let echo = TaskNode::new("echo".to_string(), "echo".to_string(), vec![]);
+        let explanation = explain_node(
+            &echo,
+            &jakefile.dir,
+            &cache,
+            &artifacts,
+            &RunOptions::default(),
+        )
+        .expect("Should be able to explain the task");
+        assert_eq!(explanation.decision, Decision::Run);
+        assert_eq!(
+            explanation.reasons,
+            vec!["no caching configured: it declares neither `sources` nor `outputs`"]
+        );
+        // a parameter changes the command, while the outputs are newer than the sources
+        let debug = HashMap::from([("profile".to_string(), "debug".to_string())]);
+        let release = HashMap::from([("profile".to_string(), "release".to_string())]);
+        assert!(run_with("profile", &debug).is_ok());
+        assert_eq!(
+            explain_with("profile", &debug, false).decision,
+            Decision::UpToDate
+        );
+        let explanation = explain_with("profile", &release, false);
+        assert_eq!(explanation.decision, Decision::Run);
+        assert_eq!(
+            explanation.reasons,
+            vec!["its command changed since the last run"]
+        );
+        // only outputs, which are there
+        assert!(run_with("package", &HashMap::new()).is_ok());
+        let explanation = explain_with("package", &HashMap::new(), false);
+        assert_eq!(explanation.decision, Decision::UpToDate);
+        assert_eq!(
+            explanation.reasons,
+            vec!["its outputs exist and it declares no `sources`"]
+        );
+        std::fs::remove_dir_all("testfiles/incremental").expect("Should remove the directory");
+        std::fs::remove_dir_all("testfiles/.jake").expect("Should remove the cache");
+    }
+
+    #[test]
+    #[serial]
+    fn test_fingerprint_cache() {
+        let executor = CommandExecutor::new();
+        let dry_run_executor = DryRunExecutor::new();
+        let run = |task: &str, dry_run: bool| {
+            let run_options = RunOptions {
+                dry_run,
+                ..Default::default()
+            };
+            let executor: &dyn Executor = if dry_run {
+                &dry_run_executor
+            } else {
+                &executor
+            };
+            execute_commands(
+                Some("testfiles/incremental.toml"),
+                &[task.to_string()],
+                &HashMap::new(),
+                &[],
+                executor,
+                &run_options,
+            )
+        };
+        let runs = || {
+            std::fs::read_to_string("testfiles/incremental/ran.log")
+                .unwrap_or_default()
+                .lines()
+                .count()
+        };
+        let _ = std::fs::remove_dir_all("testfiles/incremental");
+        let _ = std::fs::remove_dir_all("testfiles/.jake");
+        std::fs::create_dir_all("testfiles/incremental/src/nested")
+            .expect("Should be able to create the directory");
+        std::fs::write("testfiles/incremental/src/a.txt", "a\n").expect("Should write file");
+        assert!(run("build", false).is_ok());
+        assert_eq!(runs(), 1);
+        // rewriting a source makes it newer than the outputs, but leaves its content as is
+        std::thread::sleep(Duration::from_millis(20));
+        std::fs::write("testfiles/incremental/src/a.txt", "a\n").expect("Should write file");
+        assert!(run("build", false).is_ok());
+        assert_eq!(runs(), 1);
+        // without outputs, the fingerprint alone decides
+        assert!(run("no-outputs", false).is_ok());
+        assert!(run("no-outputs", false).is_ok());
+        assert_eq!(runs(), 2);
+        // dry runs do not record fingerprints
+        std::fs::write("testfiles/incremental/src/a.txt", "b\n").expect("Should write file");
+        assert!(run("no-outputs", true).is_ok());
+        assert!(run("no-outputs", false).is_ok());
+        assert_eq!(runs(), 3);
+        assert!(run("build", false).is_ok());
+        assert_eq!(runs(), 4);
+        assert!(Path::new("testfiles/.jake/cache").is_dir());
+        std::fs::remove_dir_all("testfiles/incremental").expect("Should remove the directory");
+        std::fs::remove_dir_all("testfiles/.jake").expect("Should remove the cache");
+    }
+
+    #[test]
+    #[serial]
+    fn test_fingerprint_overrides_modification_times() {
+        let run = |profile: &str| {
+            execute_commands(
+                Some("testfiles/incremental.toml"),
+                &["profile".to_string()],
+                &HashMap::from([("profile".to_string(), profile.to_string())]),
+                &[],
+                &CommandExecutor::new(),
+                &RunOptions::default(),
+            )
+        };
+        let app = || {
+            std::fs::read_to_string("testfiles/incremental/out/app.txt")
+                .expect("Should be able to read file")
+        };
+        let _ = std::fs::remove_dir_all("testfiles/incremental");
+        let _ = std::fs::remove_dir_all("testfiles/.jake");
+        std::fs::create_dir_all("testfiles/incremental/src")
+            .expect("Should be able to create the directory");
+        std::fs::write("testfiles/incremental/src/a.txt", "a\n").expect("Should write file");
+        assert!(run("debug").is_ok());
+        assert_eq!(app(), "debug\n");
+        // the outputs are newer than the sources, but the command changed
+        assert!(run("release").is_ok());
+        assert_eq!(app(), "release\n");
+        std::fs::remove_dir_all("testfiles/incremental").expect("Should remove the directory");
+        std::fs::remove_dir_all("testfiles/.jake").expect("Should remove the cache");
     }
 
     #[test]
@@ -627,7 +3160,14 @@ mod tests {
     fn test_dry_run_executor_command() {
         let path = Some("testfiles/jakefile.toml");
         let executor = DryRunExecutor::new();
-        let result = execute_command(path, "say-hello", "", &executor, false);
+        let result = execute_commands(
+            path,
+            &["say-hello".to_string()],
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
+        );
         assert!(result.is_ok());
     }
 
@@ -636,7 +3176,13 @@ mod tests {
     fn test_dry_run_executor_default_command() {
         let path = Some("testfiles/withdefault.toml");
         let executor = DryRunExecutor::new();
-        let result = execute_default_command(path, "", &executor, false);
+        let result = execute_default_command(
+            path,
+            &HashMap::new(),
+            &[],
+            &executor,
+            &RunOptions::default(),
+        );
         assert!(result.is_ok());
     }
 }
@@ -1,17 +1,27 @@
 use crate::{
     initialize::write_jakefile,
-    load::{execute_command, execute_default_command, is_posix_os, list_jakefile_tasks},
-    models::{CommandExecutor, DryRunExecutor},
+    load::{
+        clean_outputs, execute_commands, execute_default_command, explain_plan, is_posix_os,
+        list_jakefile_tasks, split_options,
+    },
+    models::{CommandExecutor, DryRunExecutor, ExitError, RunOptions, TimeoutError},
     package_json::execute_script,
 };
 use anyhow::anyhow;
 use clap::Parser;
+use std::collections::HashMap;
+use std::num::NonZeroUsize;
 
+mod cache;
 mod env_vars;
+mod incremental;
 mod initialize;
+mod interpolate;
+mod interrupt;
 mod load;
 mod models;
 mod package_json;
+mod scheduler;
 
 /// Make-like task executor for Unix-based operating systems
 #[derive(Parser, Debug)]
@@ -19,8 +29,8 @@ mod package_json;
 #[command(name = "jake")]
 #[command(about, long_about = None)]
 struct Args {
-    /// Task to execute (has to be defined within jakefile.toml)
-    task: Option<String>,
+    /// Tasks to execute (have to be defined within jakefile.toml), and `name=value` task parameters
+    tasks: Vec<String>,
 
     /// Options for the command to be executed with
     #[arg(long, default_value = "", allow_hyphen_values = true)]
@@ -45,6 +55,34 @@ struct Args {
     /// Print commands that would be run without executing them
     #[arg(long, default_value_t = false)]
     dry_run: bool,
+
+    /// Maximum number of independent tasks to run in parallel (defaults to the number of CPUs)
+    #[arg(long, short, default_value = None)]
+    jobs: Option<NonZeroUsize>,
+
+    /// Arguments forwarded to the task after `--`, appended after the ones from --options
+    #[arg(last = true)]
+    args: Vec<String>,
+
+    /// Keep running the tasks that do not depend on a failed task, and report all failures at the end
+    #[arg(long, short, default_value_t = false)]
+    keep_going: bool,
+
+    /// Run tasks even when their outputs are up to date
+    #[arg(long, default_value_t = false)]
+    force: bool,
+
+    /// Remove the outputs declared by the tasks and their dependencies, after confirmation
+    #[arg(long, default_value_t = false)]
+    clean: bool,
+
+    /// Do not ask for confirmation before removing outputs with --clean
+    #[arg(long, short, default_value_t = false)]
+    yes: bool,
+
+    /// Print whether each task would run and why, without running anything
+    #[arg(long, default_value_t = false)]
+    explain: bool,
 }
 
 fn main() -> anyhow::Result<()> {
@@ -70,18 +108,101 @@ fn main() -> anyhow::Result<()> {
         Box::new(CommandExecutor::new())
     };
     if args.js {
-        if let Some(script_name) = args.task {
-            execute_script(None, script_name, args.env, executor.as_ref())?;
-        } else {
-            return Err(anyhow!(
-                "No script name provided, please provide one or, if you wish to execute the default command from jakefile.toml, do not pass the `--js` flag."
-            ));
+        let mut script_names = args.tasks.into_iter();
+        match (script_names.next(), script_names.next()) {
+            (Some(script_name), None) => {
+                let result = execute_script(None, script_name, args.env, executor.as_ref());
+                exit_on_task_failure(result)?;
+            }
+            (Some(_), Some(_)) => {
+                return Err(anyhow!(
+                    "Only one script can be executed at a time with the `--js` flag."
+                ));
+            }
+            (None, _) => {
+                return Err(anyhow!(
+                    "No script name provided, please provide one or, if you wish to execute the default command from jakefile.toml, do not pass the `--js` flag."
+                ));
+            }
         }
         return Ok(());
     }
-    match args.task {
-        Some(t) => execute_command(None, &t, &args.options, executor.as_ref(), args.env)?,
-        None => execute_default_command(None, &args.options, executor.as_ref(), args.env)?,
+    if args.clean {
+        let (tasks, params) = split_params(args.tasks);
+        return clean_outputs(None, &tasks, &params, args.yes, args.dry_run);
+    }
+    // dry runs are kept serial so that commands are printed in a stable order
+    let jobs = if args.dry_run {
+        1
+    } else {
+        args.jobs
+            .or_else(|| std::thread::available_parallelism().ok())
+            .map_or(1, NonZeroUsize::get)
+    };
+    let run_options = RunOptions {
+        load_env: args.env,
+        jobs,
+        keep_going: args.keep_going,
+        force: args.force,
+        dry_run: args.dry_run,
+    };
+    let mut forwarded_args = split_options(&args.options)?;
+    forwarded_args.extend(args.args);
+    let (tasks, params) = split_params(args.tasks);
+    if args.explain {
+        return explain_plan(None, &tasks, &params, &forwarded_args, args.force);
+    }
+    interrupt::install_handler();
+    let result = if tasks.is_empty() {
+        execute_default_command(
+            None,
+            &params,
+            &forwarded_args,
+            executor.as_ref(),
+            &run_options,
+        )
+    } else {
+        execute_commands(
+            None,
+            &tasks,
+            &params,
+            &forwarded_args,
+            executor.as_ref(),
+            &run_options,
+        )
+    };
+    exit_on_task_failure(result)
+}
+
+/// Separates task names from `name=value` parameters in the positional arguments.
+fn split_params(words: Vec<String>) -> (Vec<String>, HashMap<String, String>) {
+    let mut tasks = vec![];
+    let mut params = HashMap::new();
+    for word in words {
+        match word.split_once('=') {
+            Some((name, value)) => {
+                params.insert(name.to_string(), value.to_string());
+            }
+            None => tasks.push(word),
+        }
+    }
+    (tasks, params)
+}
+
+/// Exits with the failing command's status code, so that jake's exit code matches the child's.
+fn exit_on_task_failure(result: anyhow::Result<()>) -> anyhow::Result<()> {
+    if let Err(e) = &result {
+        let code = if let Some(exit_error) = e.downcast_ref::<ExitError>() {
+            Some(exit_error.code)
+        } else if e.downcast_ref::<TimeoutError>().is_some() {
+            Some(TimeoutError::EXIT_CODE)
+        } else {
+            None
+        };
+        if let Some(code) = code {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(code);
+        }
     }
-    Ok(())
+    result
 }
@@ -1,9 +1,98 @@
-use std::collections::HashSet;
+use std::collections::HashMap;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::hash::{BuildHasher, Hasher};
+use std::io::Write;
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::PathBuf;
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use crate::env_vars::dotenv_to_hashmap;
+use anyhow::anyhow;
 
-pub trait Executor {
-    fn execute(&self, main_command: &str, args: Vec<&str>, load_env: bool) -> anyhow::Result<()>;
+use crate::interrupt::{interrupt_count, interrupted};
+
+pub trait Executor: Sync {
+    fn execute(
+        &self,
+        main_command: &str,
+        args: Vec<&str>,
+        dotenv: &HashMap<String, String>,
+        options: &TaskOptions,
+    ) -> anyhow::Result<()>;
+
+    /// Starts the command of a background task and returns once it is ready, leaving
+    /// it running until the returned handle is dropped.
+    ///
+    /// Executors that cannot run commands in the background run them to completion.
+    fn start(
+        &self,
+        main_command: &str,
+        args: Vec<&str>,
+        dotenv: &HashMap<String, String>,
+        options: &TaskOptions,
+        _readiness: &Readiness,
+    ) -> anyhow::Result<BackgroundProcess> {
+        self.execute(main_command, args, dotenv, options)?;
+        Ok(BackgroundProcess::finished())
+    }
+}
+
+/// Per-task settings that affect how a task's command is executed.
+#[derive(Clone, Default)]
+pub struct TaskOptions {
+    /// Maximum time the command is allowed to run before being terminated.
+    pub timeout: Option<Duration>,
+    /// Directory the command runs in, instead of the current one.
+    pub dir: Option<PathBuf>,
+    /// Environment variables set for the command, taking precedence over both the
+    /// process environment and the variables loaded from a .env file.
+    pub env: Vec<(String, String)>,
+    /// Shell program and arguments the command is passed to, `sh -c` when not set.
+    pub shell: Option<Vec<String>>,
+    /// Interpreter (program and arguments) a script recipe is run with. When set, the
+    /// task's command is a script body that is written to a temporary file and passed
+    /// to the interpreter, instead of being run by the shell.
+    pub interpreter: Option<Vec<String>>,
+}
+
+impl TaskOptions {
+    /// The shell the command runs with: the configured one, or `sh -c`.
+    pub fn effective_shell(&self) -> Vec<&str> {
+        match &self.shell {
+            Some(shell) => shell.iter().map(String::as_str).collect(),
+            None => DEFAULT_SHELL.to_vec(),
+        }
+    }
+}
+
+const DEFAULT_SHELL: [&str; 2] = ["sh", "-c"];
+
+/// When a background task is considered ready, so that the tasks depending on it can start.
+#[derive(Clone)]
+pub struct Readiness {
+    /// Condition polled until it is met, or `None` to be ready as soon as the task starts.
+    pub check: Option<ReadyCheck>,
+    /// Maximum time to wait for the check to pass.
+    pub timeout: Duration,
+}
+
+impl Readiness {
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+}
+
+#[derive(Clone)]
+pub enum ReadyCheck {
+    /// A TCP port on localhost accepts connections.
+    Port(u16),
+    /// A file exists.
+    File(PathBuf),
+    /// A command exits successfully. It runs with the task's shell, directory and environment.
+    Command(String),
 }
 
 pub struct CommandExecutor;
@@ -24,56 +113,472 @@ impl DryRunExecutor {
 }
 
 impl Executor for DryRunExecutor {
-    fn execute(&self, main_command: &str, args: Vec<&str>, _load_env: bool) -> anyhow::Result<()> {
-        let full_command = std::iter::once(main_command)
-            .chain(args.into_iter())
-            .collect::<Vec<&str>>()
-            .join(" ");
-        println!("{}", full_command);
+    fn execute(
+        &self,
+        main_command: &str,
+        args: Vec<&str>,
+        _dotenv: &HashMap<String, String>,
+        options: &TaskOptions,
+    ) -> anyhow::Result<()> {
+        if let Some(interpreter) = &options.interpreter {
+            let invocation = std::iter::once("<script>")
+                .chain(args)
+                .collect::<Vec<&str>>()
+                .join(" ");
+            println!("[{}] {}", interpreter.join(" "), invocation);
+            println!("{}", main_command.trim_end());
+            return Ok(());
+        }
+        let full_command = append_args(main_command, &args)?;
+        // the default shell is implied, so that plain tasks print just their command
+        if options.shell.is_some() {
+            println!("[{}] {}", options.effective_shell().join(" "), full_command);
+        } else {
+            println!("{}", full_command);
+        }
         Ok(())
     }
+
+    fn start(
+        &self,
+        main_command: &str,
+        args: Vec<&str>,
+        dotenv: &HashMap<String, String>,
+        options: &TaskOptions,
+        _readiness: &Readiness,
+    ) -> anyhow::Result<BackgroundProcess> {
+        self.execute(main_command, args, dotenv, options)?;
+        println!("(kept running in the background)");
+        Ok(BackgroundProcess::finished())
+    }
+}
+
+/// Appends the extra options to a command, leaving the command itself untouched.
+///
+/// Each option is quoted, so that it reaches the command as a single argument and
+/// the shell never interprets its metacharacters. Options cannot be appended to a
+/// multi-line command, whose last line may be the end of a heredoc or of a line
+/// continuation.
+pub fn append_args(command: &str, args: &[&str]) -> anyhow::Result<String> {
+    if args.is_empty() {
+        return Ok(command.to_string());
+    }
+    let command = command.trim();
+    if command.contains('\n') {
+        return Err(anyhow!(
+            "Extra options cannot be forwarded to a multi-line command: use a `script`, which receives them as arguments"
+        ));
+    }
+    Ok(format!("{} {}", command, shell_words::join(args)))
 }
 
+/// How often a child with a deadline is polled for completion.
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+/// Time given to a timed out process group to exit after SIGTERM, before SIGKILL is sent.
+const KILL_GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+/// How often the readiness check of a background task is polled.
+const READY_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
 impl Executor for CommandExecutor {
-    fn execute(&self, main_command: &str, args: Vec<&str>, load_env: bool) -> anyhow::Result<()> {
-        let mut command_args = args;
-        command_args.insert(0, main_command);
-        let full_command = command_args.join(" ");
-        let mut cmd = if !load_env {
-            std::process::Command::new("sh")
-                .arg("-c")
-                .arg(full_command)
-                .stdin(std::process::Stdio::inherit())
-                .stdout(std::process::Stdio::inherit())
-                .stderr(std::process::Stdio::inherit())
-                .spawn()?
+    fn execute(
+        &self,
+        main_command: &str,
+        args: Vec<&str>,
+        dotenv: &HashMap<String, String>,
+        options: &TaskOptions,
+    ) -> anyhow::Result<()> {
+        // kept alive until the command has completed, and removed when dropped
+        let (mut command, _script_file) = build_command(main_command, args, dotenv, options)?;
+        let status = match options.timeout {
+            None => command.spawn()?.wait()?,
+            Some(timeout) => {
+                // a dedicated process group lets us terminate everything the command spawned
+                isolate(&mut command);
+                wait_with_timeout(command.spawn()?, timeout)?
+            }
+        };
+        if status.success() {
+            Ok(())
         } else {
-            let env_vars = dotenv_to_hashmap()?;
-            std::process::Command::new("sh")
-                .arg("-c")
-                .arg(full_command)
-                .envs(env_vars)
-                .stdin(std::process::Stdio::inherit())
-                .stdout(std::process::Stdio::inherit())
-                .stderr(std::process::Stdio::inherit())
-                .spawn()?
+            Err(ExitError::from(status).into())
+        }
+    }
+
+    fn start(
+        &self,
+        main_command: &str,
+        args: Vec<&str>,
+        dotenv: &HashMap<String, String>,
+        options: &TaskOptions,
+        readiness: &Readiness,
+    ) -> anyhow::Result<BackgroundProcess> {
+        let (mut command, script_file) = build_command(main_command, args, dotenv, options)?;
+        isolate(&mut command);
+        let mut process = BackgroundProcess {
+            child: Some(command.spawn()?),
+            _script_file: script_file,
         };
-        cmd.wait()?;
-        Ok(())
+        let deadline = Instant::now() + readiness.timeout;
+        loop {
+            // the command's own process group does not receive the Ctrl-C typed in the
+            // terminal: it is terminated when the handle is dropped
+            if interrupted() {
+                return Err(ExitError {
+                    code: 128 + libc::SIGINT,
+                    signal: Some(libc::SIGINT),
+                }
+                .into());
+            }
+            if let Some(child) = process.child.as_mut()
+                && let Some(status) = child.try_wait()?
+            {
+                process.child = None;
+                let error = anyhow!("command exited before becoming ready");
+                return Err(if status.success() {
+                    error
+                } else {
+                    anyhow::Error::from(ExitError::from(status)).context(error)
+                });
+            }
+            if is_ready(readiness.check.as_ref(), deadline, dotenv, options)? {
+                return Ok(process);
+            }
+            if Instant::now() >= deadline {
+                return Err(anyhow!(
+                    "command did not become ready within {:?}",
+                    readiness.timeout
+                ));
+            }
+            thread::sleep(READY_POLL_INTERVAL);
+        }
+    }
+}
+
+/// Builds the command that runs a task: its script with the interpreter, or its
+/// command with the shell. The script file, if any, must be kept until the command
+/// has completed.
+fn build_command(
+    main_command: &str,
+    args: Vec<&str>,
+    dotenv: &HashMap<String, String>,
+    options: &TaskOptions,
+) -> anyhow::Result<(Command, Option<ScriptFile>)> {
+    let mut script_file = None;
+    let mut command = if let Some(interpreter) = &options.interpreter {
+        let script = ScriptFile::write(main_command)?;
+        let mut command = Command::new(&interpreter[0]);
+        command.args(&interpreter[1..]).arg(&script.path).args(args);
+        script_file = Some(script);
+        command
+    } else {
+        let full_command = append_args(main_command, &args)?;
+        let shell = options.effective_shell();
+        let mut command = Command::new(shell[0]);
+        command.args(&shell[1..]).arg(full_command);
+        command
+    };
+    command
+        .stdin(Stdio::inherit())
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit());
+    command.envs(dotenv);
+    command.envs(options.env.iter().map(|(k, v)| (k, v)));
+    if let Some(dir) = &options.dir {
+        if !dir.is_dir() {
+            return Err(anyhow!(
+                "Working directory {} does not exist",
+                dir.display()
+            ));
+        }
+        command.current_dir(dir);
+    }
+    Ok((command, script_file))
+}
+
+/// Runs a command in its own process group. Such a group is not the terminal's
+/// foreground group, so reading from the terminal would stop the command (SIGTTIN):
+/// its stdin is empty instead.
+fn isolate(command: &mut Command) {
+    command.process_group(0).stdin(Stdio::null());
+}
+
+/// Whether a readiness check passes. A check command still running at the deadline,
+/// or when the run is interrupted, is killed and counts as failed.
+fn is_ready(
+    check: Option<&ReadyCheck>,
+    deadline: Instant,
+    dotenv: &HashMap<String, String>,
+    options: &TaskOptions,
+) -> anyhow::Result<bool> {
+    Ok(match check {
+        None => true,
+        Some(ReadyCheck::Port(port)) => {
+            let address = SocketAddr::from((Ipv4Addr::LOCALHOST, *port));
+            TcpStream::connect_timeout(&address, READY_POLL_INTERVAL).is_ok()
+        }
+        Some(ReadyCheck::File(path)) => path.exists(),
+        Some(ReadyCheck::Command(check)) => {
+            let check_options = TaskOptions {
+                interpreter: None,
+                ..options.clone()
+            };
+            let (mut command, _) = build_command(check, vec![], dotenv, &check_options)?;
+            isolate(&mut command);
+            let mut child = command
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .spawn()?;
+            loop {
+                if let Some(status) = child.try_wait()? {
+                    break status.success();
+                }
+                if Instant::now() >= deadline || interrupted() {
+                    // SAFETY: kill(2) has no memory safety requirements
+                    unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL) };
+                    child.wait()?;
+                    break false;
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+    })
+}
+
+/// A command running in the background, in its own process group. The whole group is
+/// terminated when the handle is dropped.
+pub struct BackgroundProcess {
+    child: Option<Child>,
+    _script_file: Option<ScriptFile>,
+}
+
+impl BackgroundProcess {
+    /// Handle of a command that is not running (anymore).
+    pub fn finished() -> Self {
+        Self {
+            child: None,
+            _script_file: None,
+        }
+    }
+}
+
+impl Drop for BackgroundProcess {
+    fn drop(&mut self) {
+        if let Some(child) = self.child.as_mut() {
+            let _ = terminate_process_group(child);
+        }
+    }
+}
+
+/// Temporary file holding the body of a script recipe, deleted when dropped.
+struct ScriptFile {
+    path: PathBuf,
+}
+
+impl ScriptFile {
+    /// Writes the body to a new file with a random name, readable by the current user
+    /// only. The file is always created, never opened, so that nothing planted at its
+    /// path (such as a symlink) is written through.
+    fn write(body: &str) -> std::io::Result<Self> {
+        const ATTEMPTS: usize = 100;
+        for _ in 0..ATTEMPTS {
+            // std's hasher is seeded randomly, which makes the name unpredictable
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u32(std::process::id());
+            let path = std::env::temp_dir().join(format!("jake-script-{:016x}", hasher.finish()));
+            let file = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .mode(0o600)
+                .open(&path);
+            match file {
+                Ok(mut file) => {
+                    let script = Self { path };
+                    file.write_all(body.as_bytes())?;
+                    return Ok(script);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        Err(std::io::Error::new(
+            std::io::ErrorKind::AlreadyExists,
+            "could not create a temporary script file",
+        ))
+    }
+}
+
+impl Drop for ScriptFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+fn wait_with_timeout(mut child: Child, timeout: Duration) -> anyhow::Result<ExitStatus> {
+    let deadline = Instant::now() + timeout;
+    let mut interrupts = interrupt_count();
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(status);
+        }
+        // the child's own process group does not receive the Ctrl-C typed in the terminal
+        if interrupt_count() > interrupts {
+            interrupts = interrupt_count();
+            // SAFETY: kill(2) has no memory safety requirements
+            unsafe { libc::kill(-(child.id() as libc::pid_t), libc::SIGINT) };
+        }
+        if Instant::now() >= deadline {
+            terminate_process_group(&mut child)?;
+            return Err(TimeoutError { timeout }.into());
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// Sends SIGTERM to the child's process group and waits for the child to exit, then
+/// sends SIGKILL to whatever is left of the group. The child itself is killed if it is
+/// still alive once the grace period has passed.
+fn terminate_process_group(child: &mut Child) -> std::io::Result<()> {
+    let group = -(child.id() as libc::pid_t);
+    let deadline = Instant::now() + KILL_GRACE_PERIOD;
+    // SAFETY: kill(2) has no memory safety requirements
+    unsafe { libc::kill(group, libc::SIGTERM) };
+    while child.try_wait()?.is_none() && Instant::now() < deadline {
+        thread::sleep(POLL_INTERVAL);
+    }
+    // SAFETY: as above
+    unsafe { libc::kill(group, libc::SIGKILL) };
+    child.wait()?;
+    Ok(())
+}
+
+/// Error returned by an executor when a command runs longer than its task's timeout.
+#[derive(Debug)]
+pub struct TimeoutError {
+    pub timeout: Duration,
+}
+
+impl TimeoutError {
+    /// Exit code used for timed out tasks, the same as GNU `timeout`.
+    pub const EXIT_CODE: i32 = 124;
+}
+
+impl fmt::Display for TimeoutError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "command timed out after {:?}", self.timeout)
+    }
+}
+
+impl std::error::Error for TimeoutError {}
+
+/// Error returned by an executor when the spawned command exits unsuccessfully.
+///
+/// `code` follows the shell convention: the child's exit code, or 128 + the signal
+/// number when the child was killed by a signal.
+#[derive(Debug)]
+pub struct ExitError {
+    pub code: i32,
+    pub signal: Option<i32>,
+}
+
+impl From<ExitStatus> for ExitError {
+    fn from(status: ExitStatus) -> Self {
+        match (status.code(), status.signal()) {
+            (Some(code), _) => Self { code, signal: None },
+            (None, Some(signal)) => Self {
+                code: 128 + signal,
+                signal: Some(signal),
+            },
+            (None, None) => Self {
+                code: 1,
+                signal: None,
+            },
+        }
+    }
+}
+
+impl fmt::Display for ExitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.signal {
+            Some(signal) => write!(f, "command terminated by signal {}", signal),
+            None => write!(f, "command exited with status {}", self.code),
+        }
+    }
+}
+
+impl std::error::Error for ExitError {}
+
+/// Options controlling how a resolved task graph is executed.
+pub struct RunOptions {
+    /// Load the variables of a .env file, once before any task runs.
+    pub load_env: bool,
+    /// Maximum number of tasks running at the same time.
+    pub jobs: usize,
+    /// Keep running the tasks that do not depend on a failed one.
+    pub keep_going: bool,
+    /// Run the tasks whose outputs are up to date too.
+    pub force: bool,
+    /// Commands are only printed, so nothing is recorded in the cache.
+    pub dry_run: bool,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        Self {
+            load_env: false,
+            jobs: 1,
+            keep_going: false,
+            force: false,
+            dry_run: false,
+        }
     }
 }
 
 pub struct TaskNode {
+    /// Identifies the node in the graph: the task's name, followed by the arguments
+    /// and parameters it is invoked with, if any, so that the same task invoked with
+    /// different inputs makes distinct nodes.
+    pub id: String,
+    pub name: String,
     pub command: String,
-    pub dependencies: HashSet<String>,
+    /// Identifiers of the nodes this one depends on, in declaration order.
+    pub dependencies: Vec<String>,
+    /// Names of the tasks that must complete before this one starts, when they are
+    /// part of the same run. Unlike dependencies, they are not run because of it.
+    pub run_after: Vec<String>,
+    pub options: TaskOptions,
+    /// Number of times the command is re-run after failing.
+    pub retries: u32,
+    /// Delay before the first retry, doubled after every failed attempt.
+    pub retry_delay: Duration,
+    /// Values of the parameters declared by the task, in declaration order.
+    pub params: Vec<(String, String)>,
+    /// Extra arguments appended to the command.
+    pub args: Vec<String>,
+    /// Set for background tasks: when the tasks depending on this one can start.
+    pub background: Option<Readiness>,
+    /// Glob patterns of the files the task reads.
+    pub sources: Vec<String>,
+    /// Glob patterns of the files the task produces.
+    pub outputs: Vec<String>,
 }
 
 impl TaskNode {
-    pub fn new(command: String, dependencies: Vec<String>) -> Self {
-        let hash_set = HashSet::from_iter(dependencies);
+    pub fn new(name: String, command: String, dependencies: Vec<String>) -> Self {
         Self {
+            id: name.clone(),
+            name,
             command,
-            dependencies: hash_set,
+            dependencies,
+            run_after: vec![],
+            options: TaskOptions::default(),
+            retries: 0,
+            retry_delay: Duration::ZERO,
+            params: vec![],
+            args: vec![],
+            background: None,
+            sources: vec![],
+            outputs: vec![],
         }
     }
 }
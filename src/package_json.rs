@@ -3,7 +3,8 @@ use std::{collections::HashMap, env, fs, path::Path};
 use anyhow::{Result, anyhow};
 use serde_json::Value;
 
-use crate::models::Executor;
+use crate::env_vars::dotenv_to_hashmap;
+use crate::models::{Executor, TaskOptions};
 
 const PACKAGE_JSON: &str = "package.json";
 
@@ -78,7 +79,12 @@ pub fn execute_script(
     let map = load_package_json(path)?;
     let scripts = load_scripts(map)?;
     let command = get_script_command(scripts, script_name)?;
-    executor.execute(&command, vec![], load_env)?;
+    let dotenv = if load_env {
+        dotenv_to_hashmap()?
+    } else {
+        HashMap::new()
+    };
+    executor.execute(&command, vec![], &dotenv, &TaskOptions::default())?;
     Ok(())
 }
 
@@ -103,7 +109,8 @@ mod tests {
             &self,
             main_command: &str,
             args: Vec<&str>,
-            _load_env: bool,
+            _dotenv: &HashMap<String, String>,
+            _options: &TaskOptions,
         ) -> anyhow::Result<()> {
             let full_command = main_command.to_owned() + " " + &args.join(" ");
             std::fs::write("package.mock", full_command)?;
@@ -147,7 +154,7 @@ mod tests {
     #[serial]
     fn test_load_package_json() {
         let path = Path::new("testfiles/test-package.json");
-        let map = load_package_json(&path).expect("Should be able to load the file");
+        let map = load_package_json(path).expect("Should be able to load the file");
         assert_eq!(map.len(), 2);
         assert!(map.contains_key("type"));
         assert!(map.contains_key("scripts"));
@@ -157,7 +164,7 @@ mod tests {
     #[serial]
     fn test_load_package_json_array() {
         let path = Path::new("testfiles/json-array.json");
-        let result = load_package_json(&path);
+        let result = load_package_json(path);
         assert!(result.is_err());
     }
 
@@ -165,7 +172,7 @@ mod tests {
     #[serial]
     fn test_load_scripts_success() {
         let path = Path::new("testfiles/test-package.json");
-        let map = load_package_json(&path).expect("Should be able to load the file");
+        let map = load_package_json(path).expect("Should be able to load the file");
         let scripts = load_scripts(map).expect("Should be able to load scripts");
         assert_eq!(scripts.len(), 2);
         assert!(scripts.contains_key("hello"));
@@ -176,13 +183,10 @@ mod tests {
     #[serial]
     fn test_load_scripts_no_scripts() {
         let path = Path::new("testfiles/not-scripts.json");
-        let map = load_package_json(&path).expect("Should be able to load the file");
+        let map = load_package_json(path).expect("Should be able to load the file");
         let result = load_scripts(map);
-        assert_eq!(
-            result
-                .is_err_and(|e| e.to_string()
-                    == "Cannot find the `scripts` key in package.json".to_string()),
-            true
+        assert!(
+            result.is_err_and(|e| e.to_string() == "Cannot find the `scripts` key in package.json")
         );
     }
 
@@ -190,31 +194,25 @@ mod tests {
     #[serial]
     fn test_load_scripts_not_json_map() {
         let path = Path::new("testfiles/not-json-map.json");
-        let map = load_package_json(&path).expect("Should be able to load the file");
+        let map = load_package_json(path).expect("Should be able to load the file");
         let result = load_scripts(map);
-        assert_eq!(
-            result.is_err_and(|e| e.to_string() == "`scripts` is not a JSON map.".to_string()),
-            true
-        );
+        assert!(result.is_err_and(|e| e.to_string() == "`scripts` is not a JSON map."));
     }
 
     #[test]
     #[serial]
     fn test_load_scripts_invalid_script() {
         let path = Path::new("testfiles/invalid-script.json");
-        let map = load_package_json(&path).expect("Should be able to load the file");
+        let map = load_package_json(path).expect("Should be able to load the file");
         let result = load_scripts(map);
-        assert_eq!(
-            result.is_err_and(|e| e.to_string() == "Encountered a non-string value.".to_string()),
-            true
-        );
+        assert!(result.is_err_and(|e| e.to_string() == "Encountered a non-string value."));
     }
 
     #[test]
     #[serial]
     fn test_get_script_command_success() {
         let path = Path::new("testfiles/test-package.json");
-        let map = load_package_json(&path).expect("Should be able to load the file");
+        let map = load_package_json(path).expect("Should be able to load the file");
         let scripts = load_scripts(map).expect("Should be able to load scripts");
         assert_eq!(scripts.len(), 2);
         let command = get_script_command(scripts.clone(), "test".to_string())
@@ -229,13 +227,10 @@ mod tests {
     #[serial]
     fn test_get_script_command_failure() {
         let path = Path::new("testfiles/test-package.json");
-        let map = load_package_json(&path).expect("Should be able to load the file");
+        let map = load_package_json(path).expect("Should be able to load the file");
         let scripts = load_scripts(map).expect("Should be able to load scripts");
         let result = get_script_command(scripts, "bye".to_string());
-        assert_eq!(
-            result.is_err_and(|e| e.to_string() == "Could not find script bye".to_string()),
-            true
-        );
+        assert!(result.is_err_and(|e| e.to_string() == "Could not find script bye"));
     }
 
     #[test]
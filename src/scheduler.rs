@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::sync::mpsc;
+use std::thread;
+
+use anyhow::{Result, anyhow};
+
+use crate::interrupt::interrupted;
+use crate::models::{ExitError, RunOptions, TaskNode};
+
+#[derive(Clone, Copy, PartialEq)]
+enum JobState {
+    Pending,
+    Running,
+    Done,
+    Failed,
+    Skipped,
+}
+
+/// Runs the tasks of a resolved graph, starting every task whose dependencies have
+/// completed as soon as a slot is free, with at most `jobs` tasks running at once.
+///
+/// `nodes` must be in a valid serial execution order (dependencies first): ready tasks
+/// are always started in that order, so with `jobs = 1` the tasks run exactly one after
+/// the other. After the first failure no new task is started, unless `keep_going` is
+/// set: in that case only the tasks depending on the failed one are skipped, and a
+/// summary is printed at the end. In both cases the first error is returned. After
+/// Ctrl-C no new task is started either.
+///
+/// A task also waits for the tasks of the graph named in its `run_after`, whether they
+/// succeed or fail, without depending on them.
+pub fn run_graph<F>(nodes: &[TaskNode], run_options: &RunOptions, run: F) -> Result<()>
+where
+    F: Fn(&TaskNode) -> Result<()> + Sync,
+{
+    let jobs = run_options.jobs.max(1);
+    let positions: HashMap<&str, usize> = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| (node.id.as_str(), i))
+        .collect();
+    let mut states = vec![JobState::Pending; nodes.len()];
+    let mut first_error: Option<anyhow::Error> = None;
+
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel();
+        let mut running = 0;
+        loop {
+            // after Ctrl-C, only the running tasks are waited for
+            if (first_error.is_none() || run_options.keep_going) && !interrupted() {
+                skip_blocked(nodes, &positions, &mut states);
+                for (i, node) in nodes.iter().enumerate() {
+                    if running >= jobs {
+                        break;
+                    }
+                    if states[i] != JobState::Pending {
+                        continue;
+                    }
+                    let ready = node.dependencies.iter().all(|dep| {
+                        positions.get(dep.as_str()).map(|&d| states[d]) == Some(JobState::Done)
+                    }) && !must_wait(node, nodes, &states);
+                    if ready {
+                        states[i] = JobState::Running;
+                        running += 1;
+                        let sender = sender.clone();
+                        let run = &run;
+                        scope.spawn(move || {
+                            // the receiver outlives every worker, so sending cannot fail
+                            let _ = sender.send((i, run(node)));
+                        });
+                    }
+                }
+            }
+            if running == 0 {
+                break;
+            }
+            let (i, result) = receiver
+                .recv()
+                .expect("Scheduler channel closed while tasks were still running");
+            running -= 1;
+            match result {
+                Ok(()) => states[i] = JobState::Done,
+                Err(e) => {
+                    states[i] = JobState::Failed;
+                    if first_error.is_none() {
+                        first_error = Some(e);
+                    }
+                }
+            }
+        }
+    });
+
+    if let Some(e) = first_error {
+        if run_options.keep_going {
+            print_summary(nodes, &states);
+        }
+        return Err(e);
+    }
+    if interrupted() {
+        return Err(ExitError {
+            code: 128 + libc::SIGINT,
+            signal: Some(libc::SIGINT),
+        }
+        .into());
+    }
+    if let Some(i) = states.iter().position(|s| *s == JobState::Pending) {
+        return Err(anyhow!(
+            "Could not schedule task {}: its dependencies never completed",
+            nodes[i].id
+        ));
+    }
+    Ok(())
+}
+
+/// Whether a task of the run that `node` must run after has not completed yet.
+fn must_wait(node: &TaskNode, nodes: &[TaskNode], states: &[JobState]) -> bool {
+    nodes.iter().zip(states).any(|(other, state)| {
+        other.name != node.name
+            && node.run_after.contains(&other.name)
+            && matches!(state, JobState::Pending | JobState::Running)
+    })
+}
+
+/// Marks as skipped every pending task that depends, even transitively, on a failed task.
+fn skip_blocked(nodes: &[TaskNode], positions: &HashMap<&str, usize>, states: &mut [JobState]) {
+    // nodes are in execution order, so a single pass propagates through the whole graph
+    for (i, node) in nodes.iter().enumerate() {
+        if states[i] != JobState::Pending {
+            continue;
+        }
+        let blocked = node.dependencies.iter().any(|dep| {
+            matches!(
+                positions.get(dep.as_str()).map(|&d| states[d]),
+                Some(JobState::Failed | JobState::Skipped)
+            )
+        });
+        if blocked {
+            states[i] = JobState::Skipped;
+        }
+    }
+}
+
+fn print_summary(nodes: &[TaskNode], states: &[JobState]) {
+    let with_state = |state: JobState| {
+        nodes
+            .iter()
+            .zip(states)
+            .filter(|(_, s)| **s == state)
+            .map(|(node, _)| node.id.as_str())
+            .collect::<Vec<&str>>()
+    };
+    let failed = with_state(JobState::Failed);
+    let skipped = with_state(JobState::Skipped);
+    eprintln!("\nFailed tasks:\n- {}", failed.join("\n- "));
+    if !skipped.is_empty() {
+        eprintln!(
+            "Skipped tasks (a dependency failed):\n- {}",
+            skipped.join("\n- ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use super::*;
+
+    fn node(name: &str, dependencies: &[&str]) -> TaskNode {
+        TaskNode::new(
+            name.to_string(),
+            format!("echo {}", name),
+            dependencies.iter().map(|d| d.to_string()).collect(),
+        )
+    }
+
+    #[test]
+    fn test_serial_execution_order() {
+        let nodes = vec![
+            node("lint", &[]),
+            node("typecheck", &[]),
+            node("test", &[]),
+            node("all", &["lint", "typecheck", "test"]),
+        ];
+        let order = Mutex::new(vec![]);
+        run_graph(&nodes, &RunOptions::default(), |n| {
+            order.lock().unwrap().push(n.name.clone());
+            Ok(())
+        })
+        .expect("Should be able to run the graph");
+        assert_eq!(
+            *order.lock().unwrap(),
+            vec!["lint", "typecheck", "test", "all"]
+        );
+    }
+
+    #[test]
+    fn test_parallel_execution_respects_limit_and_dependencies() {
+        let nodes = vec![
+            node("lint", &[]),
+            node("typecheck", &[]),
+            node("test", &[]),
+            node("all", &["lint", "typecheck", "test"]),
+        ];
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+        let finished = Mutex::new(vec![]);
+        let run_options = RunOptions {
+            jobs: 2,
+            ..Default::default()
+        };
+        run_graph(&nodes, &run_options, |n| {
+            if n.name == "all" {
+                assert_eq!(finished.lock().unwrap().len(), 3);
+            }
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            max_running.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(50));
+            running.fetch_sub(1, Ordering::SeqCst);
+            finished.lock().unwrap().push(n.name.clone());
+            Ok(())
+        })
+        .expect("Should be able to run the graph");
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+        assert_eq!(finished.lock().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn test_failure_stops_scheduling() {
+        let nodes = vec![
+            node("fails", &[]),
+            node("independent", &[]),
+            node("dependent", &["fails"]),
+        ];
+        let started = Mutex::new(vec![]);
+        let result = run_graph(&nodes, &RunOptions::default(), |n| {
+            started.lock().unwrap().push(n.name.clone());
+            if n.name == "fails" {
+                return Err(anyhow!("boom"));
+            }
+            Ok(())
+        });
+        assert!(result.is_err_and(|e| e.to_string() == "boom"));
+        assert_eq!(*started.lock().unwrap(), vec!["fails"]);
+    }
+
+    #[test]
+    fn test_keep_going_runs_independent_tasks() {
+        let nodes = vec![
+            node("fails", &[]),
+            node("dependent", &["fails"]),
+            node("transitive", &["dependent"]),
+            node("independent", &[]),
+            node("also-fails", &[]),
+        ];
+        let run_options = RunOptions {
+            keep_going: true,
+            ..Default::default()
+        };
+        let started = Mutex::new(vec![]);
+        let result = run_graph(&nodes, &run_options, |n| {
+            started.lock().unwrap().push(n.name.clone());
+            if n.name.ends_with("fails") {
+                return Err(anyhow!("{} failed", n.name));
+            }
+            Ok(())
+        });
+        assert!(result.is_err_and(|e| e.to_string() == "fails failed"));
+        assert_eq!(
+            *started.lock().unwrap(),
+            vec!["fails", "independent", "also-fails"]
+        );
+    }
+
+    #[test]
+    fn test_run_after_orders_without_depending() {
+        let mut seed = node("seed", &[]);
+        seed.run_after = vec!["migrate".to_string()];
+        let nodes = vec![seed, node("migrate", &[])];
+        let run_options = RunOptions {
+            jobs: 2,
+            keep_going: true,
+            ..Default::default()
+        };
+        let started = Mutex::new(vec![]);
+        let result = run_graph(&nodes, &run_options, |n| {
+            started.lock().unwrap().push(n.name.clone());
+            if n.name == "migrate" {
+                return Err(anyhow!("migrate failed"));
+            }
+            Ok(())
+        });
+        assert!(result.is_err_and(|e| e.to_string() == "migrate failed"));
+        assert_eq!(*started.lock().unwrap(), vec!["migrate", "seed"]);
+    }
+}